@@ -1,5 +1,11 @@
+use std::time::Duration;
+
 use clap::{Parser, Subcommand};
 
+use crate::conf::{Conf, ConfFormat};
+use crate::conf_init::Preset;
+use crate::duration;
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
@@ -19,39 +25,18 @@ pub enum Command {
         #[arg(long, default_value = "false")]
         dry_run: bool,
 
-        /// Force include file globs into backup.
-        /// Allows multiple globs. [--allowlist-glob ... --allowlist-glob ...]
-        #[arg(long)]
-        allowlist_glob: Vec<String>,
-
-        /// Force include file paths into backup.
-        /// Allows multiple paths. [--allowlist-path ./1 --allowlist-path ./2]
-        #[arg(long)]
-        allowlist_path: Vec<String>,
-
-        /// Skip file globs from checking.
-        /// Difference with allowlist is that if condition
-        /// met than program wont do processing for child directories.
-        /// Allows multiple globs. [--skip-glob ... --skip-glob ...]
-        #[arg(long)]
-        skip_glob: Vec<String>,
+        #[command(flatten)]
+        options: Options,
 
-        /// Skip file paths from checking.
-        /// Difference with allowlist is that if condition
-        /// met than program wont do processing for child directories.
-        /// Allows multiple paths. [--skip-path ./1 --skip-path ./2]
-        #[arg(long)]
-        skip_path: Vec<String>,
-
-        /// Skip errors when adding or checking exclusion.
-        /// In case of for example insufficient permissions.
-        #[arg(long, default_value = "true")]
-        skip_errors: bool,
+        /// Print only matched paths, each terminated by NUL instead of newline.
+        /// Other messages are printed to stderr
+        #[arg(long, default_value = "false")]
+        print0: bool,
 
-        /// Path that should be removed from time machine backup.
-        /// Allows multiple paths. [--exclude-path ./1 --exclude-path ./2]
+        /// Save options to configuration file instead of running.
+        /// Format is detected from the file extension
         #[arg(long)]
-        exclude_path: Vec<String>,
+        save_conf: Option<String>,
     },
 
     /// Runs command in given directory and shows files which would be excluded from backup. Alias for 'run --dry-run'
@@ -60,58 +45,78 @@ pub enum Command {
         #[arg(long)]
         path: Vec<String>,
 
-        /// Force include file globs into backup. [--allowlist-glob ... --allowlist-glob ...]
-        #[arg(long)]
-        allowlist_glob: Vec<String>,
-
-        /// Force include file paths into backup. [--allowlist-path ./1 --allowlist-path ./2]
-        #[arg(long)]
-        allowlist_path: Vec<String>,
+        #[command(flatten)]
+        options: Options,
 
-        /// Skip file globs from checking.
-        /// Difference with allowlist is that if condition
-        /// met than program won't do processing for child directories
-        /// [--skip-glob ... --skip-glob ...]
-        #[arg(long)]
-        skip_glob: Vec<String>,
-
-        /// Skip file paths from checking.
-        /// Difference with allowlist is that if condition
-        /// met than program won't do processing for child directories
-        /// [--skip-path ./1 --skip-path ./2]
-        #[arg(long)]
-        skip_path: Vec<String>,
-
-        /// Skip errors when adding or checking exclusion.
-        /// In case of for example insufficient permissions.
-        #[arg(long, default_value = "true")]
-        skip_errors: bool,
-
-        /// Path that should be removed from time machine backup
-        #[arg(long)]
-        exclude_path: Vec<String>,
+        /// Print only matched paths, each terminated by NUL instead of newline.
+        /// Other messages are printed to stderr
+        #[arg(long, default_value = "false")]
+        print0: bool,
     },
 
     /// Runs command with a configuration file
     Conf {
-        /// Configuration file path
-        #[arg(long)]
-        path: String,
+        /// Configuration file path. If not set, configuration is discovered:
+        /// $TMBLISS_CONFIG, $XDG_CONFIG_HOME/tmbliss/config.*, ~/.tmbliss.json.
+        /// System-wide /etc/tmbliss/config.* and TMBLISS_* environment variables are merged in
+        #[arg(long, global = true)]
+        path: Option<String>,
 
         /// Dry run. Overrides configuration file option
-        #[arg(long)]
+        #[arg(long, global = true)]
         dry_run: Option<bool>,
+
+        /// Configuration file format. Detected from the file extension if not set
+        #[arg(long, value_enum, global = true)]
+        format: Option<ConfFormat>,
+
+        /// Profile of the configuration file to run. Allows multiple profiles.
+        /// If not set, profiles selected by hostname are run, or top-level options
+        /// if no profile matches. [--profile dev --profile work]
+        #[arg(long, global = true)]
+        profile: Vec<String>,
+
+        #[command(flatten)]
+        options: Options,
+
+        #[command(subcommand)]
+        command: Option<ConfCommand>,
     },
 
     /// Same as 'conf' but with logging suitable for a service
     Service {
-        /// Configuration file path
+        /// Configuration file path. Discovered the same way as in 'conf' if not set
         #[arg(long)]
-        path: String,
+        path: Option<String>,
 
         /// Dry run. Overrides configuration file option
         #[arg(long)]
         dry_run: Option<bool>,
+
+        /// Configuration file format. Detected from the file extension if not set
+        #[arg(long, value_enum)]
+        format: Option<ConfFormat>,
+
+        /// Profile of the configuration file to run. Allows multiple profiles.
+        /// If not set, profiles selected by hostname are run, or top-level options
+        /// if no profile matches. [--profile dev --profile work]
+        #[arg(long)]
+        profile: Vec<String>,
+
+        #[command(flatten)]
+        options: Options,
+    },
+
+    /// Exclude paths from backup right away. With --ttl exclusion is lifted
+    /// by the first 'run' or 'service' pass after it expires
+    Exclude {
+        /// Paths to exclude
+        #[arg(required = true)]
+        path: Vec<String>,
+
+        /// Lift exclusion after this time. [--ttl 14d]
+        #[arg(long, value_parser = duration::parse)]
+        ttl: Option<Duration>,
     },
 
     /// Reset all exclusions in given directory
@@ -131,6 +136,14 @@ pub enum Command {
         /// Skip reset for matched paths.  [--allowlist-path ./1 --allowlist-path ./2]
         #[arg(long)]
         allowlist_path: Vec<String>,
+
+        /// Reset only exclusions made by tmbliss, keeping exclusions made by hand or other tools
+        #[arg(long, default_value = "false")]
+        only_managed: bool,
+
+        /// Reset only exclusions made by tmbliss longer than this ago. [--older-than 30d]
+        #[arg(long, value_parser = duration::parse)]
+        older_than: Option<Duration>,
     },
     /// Show excluded files starting from given directory: Alias for 'reset --dry-run'
     ShowExcluded {
@@ -145,15 +158,332 @@ pub enum Command {
         /// Skip reset for matched paths.  [--allowlist-path ./1 --allowlist-path ./2]
         #[arg(long)]
         allowlist_path: Vec<String>,
+
+        /// Show only exclusions made by tmbliss
+        #[arg(long, default_value = "false", conflicts_with = "manual")]
+        managed: bool,
+
+        /// Show only exclusions made by hand or other tools
+        #[arg(long, default_value = "false")]
+        manual: bool,
+
+        /// Show only exclusions made by tmbliss longer than this ago. [--older-than 30d]
+        #[arg(long, value_parser = duration::parse)]
+        older_than: Option<Duration>,
+    },
+    /// Check existing exclusions in given directory and print dangerous ones as JSON:
+    /// exclusions of git-tracked files, repository roots, items outside any repository
+    /// and secrets
+    Audit {
+        /// Directory path
+        #[arg(long)]
+        path: String,
+
+        /// Remove flagged exclusions
+        #[arg(long, default_value = "false")]
+        fix: bool,
+    },
+    /// Explain why a path is or isn't excluded: shows matched .gitignore line,
+    /// overriding rules and .tmbliss patterns, excluded ancestor and the final action
+    Explain {
+        /// Path to explain
+        path: String,
+
+        /// Configuration file path. Without it the path is checked from
+        /// its git repository root with default options
+        #[arg(long)]
+        conf: Option<String>,
+    },
+    /// Check paths the same way as 'explain', printing a line per path:
+    /// action, whether path is currently excluded, deciding rule and the path.
+    /// Similar to 'git check-ignore -v'
+    Check {
+        /// Paths to check
+        path: Vec<String>,
+
+        /// Read paths from standard input, one per line, instead of arguments
+        #[arg(long, conflicts_with = "path")]
+        stdin: bool,
+
+        /// Paths are NUL separated, output fields are NUL terminated
+        #[arg(short = 'z')]
+        zero: bool,
+
+        /// Configuration file path. Without it paths are checked from
+        /// their git repository root with default options
+        #[arg(long)]
+        conf: Option<String>,
+    },
+    /// List past runs of 'run', 'service' and 'conf' recorded in the state directory:
+    /// id, start time, roots and counts of new, already excluded and errored items
+    History {
+        /// Show only this many latest runs
+        #[arg(long)]
+        limit: Option<usize>,
+
+        #[command(subcommand)]
+        command: Option<HistoryCommand>,
     },
     /// Generate markdown help
     MarkdownHelp,
 }
 
+/// Options shared by commands that process directories.
+/// In 'conf' and 'service' they override options of configuration file.
+/// Global, so they can follow 'conf show' too, e.g. 'conf show --resolved --skip-path x'.
+/// `--force` isn't global as 'conf init' has its own
+#[derive(clap::Args, Debug, Default, PartialEq)]
+pub struct Options {
+    /// Force include file globs into backup.
+    /// Allows multiple globs. [--allowlist-glob ... --allowlist-glob ...]
+    #[arg(long, global = true)]
+    pub allowlist_glob: Vec<String>,
+
+    /// Force include file paths into backup.
+    /// Allows multiple paths. [--allowlist-path ./1 --allowlist-path ./2]
+    #[arg(long, global = true)]
+    pub allowlist_path: Vec<String>,
+
+    /// Force include into backup files which full path matches regex.
+    /// Allows multiple regexes. [--allowlist-regex ... --allowlist-regex ...]
+    #[arg(long, global = true)]
+    pub allowlist_regex: Vec<String>,
+
+    /// Skip file globs from checking.
+    /// Difference with allowlist is that if condition
+    /// met than program wont do processing for child directories.
+    /// Allows multiple globs. [--skip-glob ... --skip-glob ...]
+    #[arg(long, global = true)]
+    pub skip_glob: Vec<String>,
+
+    /// Skip file paths from checking.
+    /// Difference with allowlist is that if condition
+    /// met than program wont do processing for child directories.
+    /// Allows multiple paths. [--skip-path ./1 --skip-path ./2]
+    #[arg(long, global = true)]
+    pub skip_path: Vec<String>,
+
+    /// Skip files which full path matches regex from checking.
+    /// Allows multiple regexes. [--skip-regex ... --skip-regex ...]
+    #[arg(long, global = true)]
+    pub skip_regex: Vec<String>,
+
+    /// Skip errors when adding or checking exclusion.
+    /// In case of for example insufficient permissions. Enabled in 'run' and 'list' by default.
+    /// [--skip-errors, --skip-errors false]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub skip_errors: Option<bool>,
+
+    /// Path that should be removed from time machine backup.
+    /// Allows multiple paths. [--exclude-path ./1 --exclude-path ./2]
+    #[arg(long, global = true)]
+    pub exclude_path: Vec<String>,
+
+    /// Remove files which full path matches regex from time machine backup
+    /// even if they are not ignored by git.
+    /// Allows multiple regexes. [--exclude-regex ... --exclude-regex ...]
+    #[arg(long, global = true)]
+    pub exclude_regex: Vec<String>,
+
+    /// Remove files matched by glob from time machine backup
+    /// even if they are not ignored by git.
+    /// Allows multiple globs. [--exclude-glob ... --exclude-glob ...]
+    #[arg(long, global = true)]
+    pub exclude_glob: Vec<String>,
+
+    /// File with paths and globs (one per line) that should be removed from time machine backup.
+    /// `-` reads standard input
+    #[arg(long, global = true)]
+    pub exclude_from: Option<String>,
+
+    /// File with additional directory paths (one per line). `-` reads standard input
+    #[arg(long, global = true)]
+    pub paths_from: Option<String>,
+
+    /// Entries of --paths-from and --exclude-from are NUL separated, as in 'find -print0' output
+    #[arg(short = '0', long = "null", global = true, default_value = "false")]
+    pub null_separated: bool,
+
+    /// Report exclude paths that don't exist instead of failing. [--missing-ok, --missing-ok false]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub missing_ok: Option<bool>,
+
+    /// Match globs, paths and regexes case insensitively.
+    /// Detected from the volume of each path if not set. [--case-insensitive, --case-insensitive false]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub case_insensitive: Option<bool>,
+
+    /// Match globs against full absolute paths. [--absolute-globs, --absolute-globs false]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub absolute_globs: Option<bool>,
+
+    /// Don't keep built-in list of secrets and local state in backup when they are ignored by git.
+    /// [--disable-builtin-allowlist, --disable-builtin-allowlist false]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub disable_builtin_allowlist: Option<bool>,
+
+    /// Abort the run before excluding anything if it's going to add more new exclusions
+    #[arg(long, global = true)]
+    pub max_new_exclusions: Option<usize>,
+
+    /// Abort the run before excluding anything if new exclusions take more than
+    /// this percent of bytes of any of the paths
+    #[arg(long, global = true)]
+    pub max_excluded_percent: Option<f64>,
+
+    /// Path that can never be excluded, along with its contents and parents.
+    /// Allows multiple paths. [--protected-path ~/Documents --protected-path ~/Desktop]
+    #[arg(long, global = true)]
+    pub protected_path: Vec<String>,
+
+    /// Record total size of new exclusions in run history. [--history-bytes, --history-bytes false]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub history_bytes: Option<bool>,
+
+    /// Warn about probably hand-made files in items that are about to be excluded, always on in dry run. [--warn-risky, --warn-risky false]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub warn_risky: Option<bool>,
+
+    /// Exclude items even if the run trips safety guards
+    #[arg(long, default_value = "false")]
+    pub force: bool,
+
+    /// User-global .tmbliss file applied to every directory
+    #[arg(long, global = true)]
+    pub tmbliss_file: Option<String>,
+
+    /// Replace list options of configuration with the given ones instead of appending to them.
+    /// Lists that aren't given are kept
+    #[arg(long, global = true, default_value = "false")]
+    pub replace: bool,
+}
+
+impl Options {
+    /// Merges options on top of configuration
+    pub fn apply(&self, conf: &mut Conf) {
+        for (list, items) in [
+            (&mut conf.allowlist_glob, &self.allowlist_glob),
+            (&mut conf.allowlist_path, &self.allowlist_path),
+            (&mut conf.allowlist_regex, &self.allowlist_regex),
+            (&mut conf.skip_glob, &self.skip_glob),
+            (&mut conf.skip_path, &self.skip_path),
+            (&mut conf.skip_regex, &self.skip_regex),
+            (&mut conf.exclude_paths, &self.exclude_path),
+            (&mut conf.exclude_regex, &self.exclude_regex),
+            (&mut conf.exclude_glob, &self.exclude_glob),
+            (&mut conf.protected_path, &self.protected_path),
+        ] {
+            if items.is_empty() {
+                continue;
+            }
+            if self.replace {
+                list.clear();
+            }
+            list.extend(items.iter().cloned());
+        }
+        if let Some(skip_errors) = self.skip_errors {
+            conf.skip_errors = skip_errors;
+        }
+        if self.exclude_from.is_some() {
+            conf.exclude_from.clone_from(&self.exclude_from);
+        }
+        if self.paths_from.is_some() {
+            conf.paths_from.clone_from(&self.paths_from);
+        }
+        conf.null_separated |= self.null_separated;
+        if let Some(missing_ok) = self.missing_ok {
+            conf.missing_ok = missing_ok;
+        }
+        if self.case_insensitive.is_some() {
+            conf.case_insensitive = self.case_insensitive;
+        }
+        if let Some(absolute_globs) = self.absolute_globs {
+            conf.absolute_globs = absolute_globs;
+        }
+        if let Some(disable_builtin_allowlist) = self.disable_builtin_allowlist {
+            conf.disable_builtin_allowlist = disable_builtin_allowlist;
+        }
+        if self.max_new_exclusions.is_some() {
+            conf.max_new_exclusions = self.max_new_exclusions;
+        }
+        if self.max_excluded_percent.is_some() {
+            conf.max_excluded_percent = self.max_excluded_percent;
+        }
+        if let Some(history_bytes) = self.history_bytes {
+            conf.history_bytes = history_bytes;
+        }
+        if let Some(warn_risky) = self.warn_risky {
+            conf.warn_risky = warn_risky;
+        }
+        if self.tmbliss_file.is_some() {
+            conf.tmbliss_file.clone_from(&self.tmbliss_file);
+        }
+    }
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum HistoryCommand {
+    /// Show items that became excluded or stopped being excluded between two runs
+    /// and whether configuration changed. Runs are referenced by id
+    /// or by position from the end: -1 is the last run
+    Diff {
+        /// Earlier run
+        #[arg(allow_hyphen_values = true)]
+        a: String,
+
+        /// Later run
+        #[arg(allow_hyphen_values = true)]
+        b: String,
+    },
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum ConfCommand {
+    /// Show configuration layers: system-wide file, user file, included files
+    /// and environment variables. Run options of 'conf' are applied to merged configuration
+    Show {
+        /// Show merged configuration instead of separate layers
+        #[arg(long)]
+        resolved: bool,
+    },
+    /// Validate configuration: unknown options, glob and regex syntax, paths existence,
+    /// duplicate and contradictory entries. Exits with non-zero code if there are errors
+    Check {
+        /// Exit with non-zero code on warnings too
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Write commented starter configuration to --path, or to ~/.config/tmbliss/config.jsonc.
+    /// Asks for directories and presets if none are given
+    Init {
+        /// Directory to process. [--root ~/Dev --root ~/Work]
+        #[arg(long)]
+        root: Vec<String>,
+
+        /// Allowlist preset. [--preset secrets --preset databases]
+        #[arg(long, value_enum)]
+        preset: Vec<Preset>,
+
+        /// Overwrite existing configuration
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print JSON Schema of configuration for editor completion and validation
+    Schema,
+    /// Upgrade configuration file to the current version, e.g. rename options.
+    /// Prints migrated configuration unless --write is set. Comments aren't preserved
+    Migrate {
+        /// Replace the file, original is kept with .bak suffix
+        #[arg(long)]
+        write: bool,
+    },
+}
+
 #[cfg(test)]
 mod tests {
     use crate::*;
     use clap::Parser;
+    use std::time::Duration;
 
     #[test]
     fn it_parses_paths() {
@@ -163,12 +493,9 @@ mod tests {
             Command::Run {
                 path: [String::from("./1"), String::from("./2")].to_vec(),
                 dry_run: false,
-                allowlist_glob: vec![],
-                allowlist_path: vec![],
-                skip_glob: vec![],
-                skip_path: vec![],
-                skip_errors: true,
-                exclude_path: vec![],
+                options: Options::default(),
+                print0: false,
+                save_conf: None,
             }
         );
     }
@@ -191,12 +518,39 @@ mod tests {
             Command::Run {
                 path: [String::from("./")].to_vec(),
                 dry_run: true,
-                allowlist_glob: vec![String::from(".env"), String::from(".env.*")],
-                allowlist_path: vec![],
-                skip_glob: vec![],
-                skip_path: vec![],
-                skip_errors: true,
-                exclude_path: vec![],
+                options: Options {
+                    allowlist_glob: vec![String::from(".env"), String::from(".env.*")],
+                    ..Default::default()
+                },
+                print0: false,
+                save_conf: None,
+            }
+        );
+    }
+
+    #[test]
+    fn it_parses_path_lists() {
+        let args = Args::parse_from([
+            "tmbliss",
+            "list",
+            "--paths-from",
+            "-",
+            "--exclude-from",
+            "./excludes",
+            "-0",
+            "--print0",
+        ]);
+        assert_eq!(
+            args.command,
+            Command::List {
+                path: vec![],
+                options: Options {
+                    exclude_from: Some(String::from("./excludes")),
+                    paths_from: Some(String::from("-")),
+                    null_separated: true,
+                    ..Default::default()
+                },
+                print0: true,
             }
         );
     }
@@ -219,12 +573,12 @@ mod tests {
             Command::Run {
                 path: [String::from("./")].to_vec(),
                 dry_run: true,
-                allowlist_glob: vec![],
-                allowlist_path: vec![String::from("./1"), String::from("./2")],
-                skip_glob: vec![],
-                skip_path: vec![],
-                skip_errors: true,
-                exclude_path: vec![],
+                options: Options {
+                    allowlist_path: vec![String::from("./1"), String::from("./2")],
+                    ..Default::default()
+                },
+                print0: false,
+                save_conf: None,
             }
         );
     }
@@ -235,8 +589,12 @@ mod tests {
         assert_eq!(
             args.command,
             Command::Conf {
-                path: "./conf.json".to_string(),
-                dry_run: None
+                path: Some("./conf.json".to_string()),
+                dry_run: None,
+                format: None,
+                profile: vec![],
+                options: Options::default(),
+                command: None,
             }
         );
         {
@@ -247,14 +605,314 @@ mod tests {
                 "./conf.json",
                 "--dry-run",
                 "true",
+                "--format",
+                "toml",
+                "--profile",
+                "work",
             ]);
             assert_eq!(
                 args.command,
                 Command::Conf {
-                    path: "./conf.json".to_string(),
-                    dry_run: Some(true)
+                    path: Some("./conf.json".to_string()),
+                    dry_run: Some(true),
+                    format: Some(crate::conf::ConfFormat::Toml),
+                    profile: vec!["work".to_string()],
+                    options: Options::default(),
+                    command: None,
                 }
             );
         }
     }
+
+    #[test]
+    fn it_parses_conf_show() {
+        let args = Args::parse_from(["tmbliss", "conf", "show", "--resolved", "--profile", "dev"]);
+        assert_eq!(
+            args.command,
+            Command::Conf {
+                path: None,
+                dry_run: None,
+                format: None,
+                profile: vec!["dev".to_string()],
+                options: Options::default(),
+                command: Some(ConfCommand::Show { resolved: true }),
+            }
+        );
+
+        let args = Args::parse_from(["tmbliss", "conf", "show", "--resolved", "--skip-path", "x"]);
+        assert_eq!(
+            args.command,
+            Command::Conf {
+                path: None,
+                dry_run: None,
+                format: None,
+                profile: vec![],
+                options: Options {
+                    skip_path: vec!["x".to_string()],
+                    ..Options::default()
+                },
+                command: Some(ConfCommand::Show { resolved: true }),
+            }
+        );
+
+        // Subcommands that don't use run options reject them
+        let args = Args::parse_from([
+            "tmbliss",
+            "conf",
+            "schema",
+            "--skip-path",
+            "/nope",
+            "--allowlist-glob",
+            "x",
+        ]);
+        assert!(TMBliss::run(args.command).is_err());
+
+        let args = Args::parse_from(["tmbliss", "conf", "init", "--force"]);
+        assert!(matches!(
+            args.command,
+            Command::Conf {
+                command: Some(ConfCommand::Init { force: true, .. }),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn it_parses_conf_check() {
+        let args = Args::parse_from(["tmbliss", "conf", "check", "--path", "conf.toml"]);
+        assert_eq!(
+            args.command,
+            Command::Conf {
+                path: Some("conf.toml".to_string()),
+                dry_run: None,
+                format: None,
+                profile: vec![],
+                options: Options::default(),
+                command: Some(ConfCommand::Check { strict: false }),
+            }
+        );
+
+        let args = Args::parse_from(["tmbliss", "conf", "check", "--strict"]);
+        assert!(matches!(
+            args.command,
+            Command::Conf {
+                command: Some(ConfCommand::Check { strict: true }),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn it_parses_check() {
+        let args = Args::parse_from(["tmbliss", "check", "--stdin", "-z"]);
+        assert_eq!(
+            args.command,
+            Command::Check {
+                path: vec![],
+                stdin: true,
+                zero: true,
+                conf: None,
+            }
+        );
+
+        let args = Args::try_parse_from(["tmbliss", "check", "--stdin", "./a"]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn it_parses_conf_migrate() {
+        let args = Args::parse_from(["tmbliss", "conf", "migrate", "--write", "--path", "a.yaml"]);
+        assert_eq!(
+            args.command,
+            Command::Conf {
+                path: Some("a.yaml".to_string()),
+                dry_run: None,
+                format: None,
+                profile: vec![],
+                options: Options::default(),
+                command: Some(ConfCommand::Migrate { write: true }),
+            }
+        );
+    }
+
+    #[test]
+    fn it_parses_conf_init() {
+        let args = Args::parse_from([
+            "tmbliss", "conf", "init", "--root", "~/Dev", "--preset", "secrets", "--force",
+        ]);
+        assert_eq!(
+            args.command,
+            Command::Conf {
+                path: None,
+                dry_run: None,
+                format: None,
+                profile: vec![],
+                options: Options::default(),
+                command: Some(ConfCommand::Init {
+                    root: vec!["~/Dev".to_string()],
+                    preset: vec![Preset::Secrets],
+                    force: true,
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn it_parses_ownership_filters() {
+        let args = Args::parse_from([
+            "tmbliss",
+            "show-excluded",
+            "--path",
+            "~/Dev",
+            "--managed",
+            "--older-than",
+            "30d",
+        ]);
+        assert_eq!(
+            args.command,
+            Command::ShowExcluded {
+                path: "~/Dev".to_string(),
+                allowlist_glob: vec![],
+                allowlist_path: vec![],
+                managed: true,
+                manual: false,
+                older_than: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+            }
+        );
+
+        assert!(Args::try_parse_from([
+            "tmbliss",
+            "show-excluded",
+            "--path",
+            "~/Dev",
+            "--managed",
+            "--manual",
+        ])
+        .is_err());
+        assert!(Args::try_parse_from([
+            "tmbliss",
+            "reset",
+            "--path",
+            "~/Dev",
+            "--older-than",
+            "30",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn it_parses_exclude() {
+        let args = Args::parse_from(["tmbliss", "exclude", "--ttl", "14d", "./dataset"]);
+        assert_eq!(
+            args.command,
+            Command::Exclude {
+                path: vec!["./dataset".to_string()],
+                ttl: Some(Duration::from_secs(14 * 24 * 60 * 60)),
+            }
+        );
+        assert!(Args::try_parse_from(["tmbliss", "exclude", "--ttl", "14d"]).is_err());
+    }
+
+    #[test]
+    fn it_parses_history() {
+        let args = Args::parse_from(["tmbliss", "history", "--limit", "5"]);
+        assert_eq!(
+            args.command,
+            Command::History {
+                limit: Some(5),
+                command: None,
+            }
+        );
+
+        let args = Args::parse_from(["tmbliss", "history", "diff", "-2", "-1"]);
+        assert_eq!(
+            args.command,
+            Command::History {
+                limit: None,
+                command: Some(HistoryCommand::Diff {
+                    a: "-2".to_string(),
+                    b: "-1".to_string(),
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn it_parses_audit() {
+        let args = Args::parse_from(["tmbliss", "audit", "--path", "~/Dev", "--fix"]);
+        assert_eq!(
+            args.command,
+            Command::Audit {
+                path: "~/Dev".to_string(),
+                fix: true,
+            }
+        );
+    }
+
+    #[test]
+    fn it_parses_conf_overrides() {
+        let args = Args::parse_from([
+            "tmbliss",
+            "service",
+            "--skip-path",
+            "./vendor",
+            "--skip-errors",
+            "false",
+            "--missing-ok",
+            "--replace",
+            "--max-new-exclusions",
+            "100",
+            "--protected-path",
+            "~/Documents",
+            "--force",
+        ]);
+        assert_eq!(
+            args.command,
+            Command::Service {
+                path: None,
+                dry_run: None,
+                format: None,
+                profile: vec![],
+                options: Options {
+                    skip_path: vec!["./vendor".to_string()],
+                    skip_errors: Some(false),
+                    missing_ok: Some(true),
+                    replace: true,
+                    max_new_exclusions: Some(100),
+                    protected_path: vec!["~/Documents".to_string()],
+                    force: true,
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn it_applies_options() {
+        let conf = conf::Conf {
+            allowlist_glob: vec![".env".to_string()],
+            skip_glob: vec!["vendor".to_string()],
+            skip_errors: true,
+            ..Default::default()
+        };
+        let options = |replace: bool| Options {
+            allowlist_glob: vec!["*.sqlite".to_string()],
+            skip_errors: Some(false),
+            case_insensitive: Some(true),
+            replace,
+            ..Default::default()
+        };
+
+        let mut appended = conf.clone();
+        options(false).apply(&mut appended);
+        assert_eq!(appended.allowlist_glob, [".env", "*.sqlite"]);
+        assert_eq!(appended.skip_glob, ["vendor"]);
+        assert!(!appended.skip_errors);
+        assert_eq!(appended.case_insensitive, Some(true));
+
+        let mut replaced = conf.clone();
+        options(true).apply(&mut replaced);
+        assert_eq!(replaced.allowlist_glob, ["*.sqlite"]);
+        assert_eq!(replaced.skip_glob, ["vendor"]);
+    }
 }
@@ -0,0 +1,199 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use glob_match::glob_match;
+use serde::Serialize;
+
+use crate::git::Git;
+use crate::protect::BUILTIN_ALLOWLIST;
+use crate::recursive_directory_iterator::RecursiveDirectoryIterator;
+use crate::time_machine::TimeMachine;
+
+/// Reason why an existing exclusion is dangerous
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    /// Excluded item is or contains files tracked by git
+    TrackedFiles,
+    /// Excluded item is a repository root
+    RepositoryRoot,
+    /// Excluded item isn't inside any repository, so it isn't a build artifact
+    OutsideRepository,
+    /// Excluded file matches built-in list of secrets
+    Secret,
+}
+
+/// Exclusion flagged by audit
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Finding {
+    pub path: PathBuf,
+    pub problems: Vec<Problem>,
+    /// Whether the exclusion was removed
+    pub fixed: bool,
+}
+
+/// Walks exclusions in the directory, as `reset` does, and reports dangerous ones.
+/// Exclusions tmbliss made on purpose, with exclude rules, `exclude_paths`
+/// or `tmbliss exclude`, are never flagged. Flagged exclusions are removed if `fix` is set
+pub fn audit(path: &Path, fix: bool) -> Result<Vec<Finding>> {
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("Can't canonicalize path {}", path.display()))?;
+    let findings = RefCell::new(vec![]);
+    // Tracked files of each repository, listed once
+    let tracked = RefCell::new(HashMap::new());
+    let check = |item: &PathBuf| -> Result<bool> {
+        if !TimeMachine::is_excluded(item)?
+            || TimeMachine::owner(item)?.is_some_and(|owner| owner.is_deliberate())
+        {
+            return Ok(true);
+        }
+        let problems = problems(item, &mut tracked.borrow_mut());
+        if problems.is_empty() {
+            return Ok(true);
+        }
+        if fix {
+            TimeMachine::remove_exclusion(item)?;
+        }
+        findings.borrow_mut().push(Finding {
+            path: item.to_path_buf(),
+            problems,
+            fixed: fix,
+        });
+        Ok(true)
+    };
+
+    check(&path)?;
+    RecursiveDirectoryIterator {
+        path: &path,
+        op: &check,
+    }
+    .iterate()?;
+
+    let mut findings = findings.into_inner();
+    findings.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(findings)
+}
+
+fn problems(item: &Path, tracked: &mut HashMap<PathBuf, BTreeSet<PathBuf>>) -> Vec<Problem> {
+    let mut problems = vec![];
+    let root = Git::root(item);
+    if let Some(root) = &root {
+        let files = tracked
+            .entry(root.clone())
+            .or_insert_with(|| Git::tracked_files(root));
+        // Files inside the item follow it in path order
+        if files
+            .range(item.to_path_buf()..)
+            .next()
+            .is_some_and(|file| file.starts_with(item))
+        {
+            problems.push(Problem::TrackedFiles);
+        }
+    }
+    match root {
+        Some(root) if root == item => problems.push(Problem::RepositoryRoot),
+        Some(_) => {}
+        None => problems.push(Problem::OutsideRepository),
+    }
+    let is_secret = item.is_file()
+        && item.file_name().is_some_and(|name| {
+            let name = name.to_string_lossy();
+            BUILTIN_ALLOWLIST
+                .iter()
+                .any(|pattern| glob_match(pattern, &name))
+        });
+    if is_secret {
+        problems.push(Problem::Secret);
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process::Command};
+
+    use crate::owner::{Owner, OwnerKind};
+    use crate::test_utils::TestDir;
+
+    use super::*;
+
+    #[test]
+    fn it_audits_exclusions() {
+        let dir = TestDir::new();
+        let path = dir.path().canonicalize().unwrap();
+        let repo = path.join("repo");
+        fs::create_dir_all(repo.join("src")).unwrap();
+        fs::create_dir_all(repo.join("target")).unwrap();
+        fs::create_dir_all(path.join("outside")).unwrap();
+        fs::write(repo.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(repo.join(".env"), "TOKEN=1").unwrap();
+        for args in [vec!["init"], vec!["add", "src/main.rs"]] {
+            Command::new("git")
+                .args(args)
+                .current_dir(&repo)
+                .output()
+                .unwrap();
+        }
+        fs::create_dir_all(path.join("dataset")).unwrap();
+        fs::create_dir_all(path.join("archive")).unwrap();
+        fs::create_dir_all(path.join("stale")).unwrap();
+        fs::write(path.join("image.iso"), "").unwrap();
+        for item in ["repo", "repo/src", "repo/target", "repo/.env", "outside"] {
+            TimeMachine::add_exclusion(&path.join(item), None).unwrap();
+        }
+        // Exclusions made on purpose outside of any repository aren't flagged
+        let now = chrono::Utc::now();
+        let owner = |kind: OwnerKind, reason: &str| Owner {
+            run: Owner::run_id(now),
+            time: now,
+            kind,
+            reason: reason.to_string(),
+            expires: None,
+        };
+        for (item, owner) in [
+            ("dataset", owner(OwnerKind::Manual, "exclude")),
+            ("archive", owner(OwnerKind::ExcludePaths, "exclude_paths")),
+            (
+                "image.iso",
+                owner(OwnerKind::Rule, "conf: exclude glob *.iso"),
+            ),
+            ("stale", owner(OwnerKind::Gitignore, "gitignore: stale")),
+        ] {
+            TimeMachine::add_exclusion(&path.join(item), Some(&owner)).unwrap();
+        }
+
+        let finding = |item: &str, problems: Vec<Problem>, fixed: bool| Finding {
+            path: path.join(item),
+            problems,
+            fixed,
+        };
+        let expected = |fixed: bool| {
+            vec![
+                finding("outside", vec![Problem::OutsideRepository], fixed),
+                finding(
+                    "repo",
+                    vec![Problem::TrackedFiles, Problem::RepositoryRoot],
+                    fixed,
+                ),
+                finding("repo/.env", vec![Problem::Secret], fixed),
+                finding("repo/src", vec![Problem::TrackedFiles], fixed),
+                finding("stale", vec![Problem::OutsideRepository], fixed),
+            ]
+        };
+
+        assert_eq!(audit(&path, false).unwrap(), expected(false));
+        assert!(TimeMachine::is_excluded(&repo).unwrap());
+        assert_eq!(audit(&path, true).unwrap(), expected(true));
+        assert!(!TimeMachine::is_excluded(&repo).unwrap());
+        assert!(TimeMachine::is_excluded(&repo.join("target")).unwrap());
+        assert!(TimeMachine::is_excluded(&path.join("dataset")).unwrap());
+        assert!(TimeMachine::is_excluded(&path.join("archive")).unwrap());
+        assert!(TimeMachine::is_excluded(&path.join("image.iso")).unwrap());
+        assert!(audit(&path, false).unwrap().is_empty());
+    }
+}
@@ -1,24 +1,57 @@
-use std::{fs::File, io::BufReader};
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs::{self, File},
+    io::{self, Read},
+    path::Path,
+};
 
-use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
 
-#[derive(Serialize, Deserialize, Clone)]
+use crate::expand;
+use crate::migrate;
+
+/// Configuration of tmbliss
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
 pub struct Conf {
+    /// Version of configuration format. Configuration without version is version 1,
+    /// `tmbliss conf migrate` upgrades it to the current version
+    #[serde(default)]
+    pub version: Option<u32>,
+
+    /// Directories to process
     pub paths: Vec<String>,
 
+    /// Globs for items that should be kept in backup even if they are ignored by git
     #[serde(default)]
     pub allowlist_glob: Vec<String>,
 
+    /// Paths that should be kept in backup even if they are ignored by git
     #[serde(default)]
     pub allowlist_path: Vec<String>,
 
+    /// Regular expressions for full paths of items that should be kept in backup
+    /// even if they are ignored by git
+    #[serde(default)]
+    pub allowlist_regex: Vec<String>,
+
+    /// Globs for items that shouldn't be processed at all, along with their children
     #[serde(default)]
     pub skip_glob: Vec<String>,
 
+    /// Paths that shouldn't be processed at all, along with their children
     #[serde(default)]
     pub skip_path: Vec<String>,
 
+    /// Regular expressions for full paths of items that shouldn't be processed at all,
+    /// along with their children
+    #[serde(default)]
+    pub skip_regex: Vec<String>,
+
     /// Should program run in dry run mode. No changes made
     #[serde(default)]
     pub dry_run: bool,
@@ -30,35 +63,373 @@ pub struct Conf {
     /// Paths that should be removed from time machine backup
     #[serde(default)]
     pub exclude_paths: Vec<String>,
+
+    /// Regular expressions for items that should be removed from time machine backup
+    /// even if they are not ignored by git
+    #[serde(default)]
+    pub exclude_regex: Vec<String>,
+
+    /// Globs for items that should be removed from time machine backup
+    /// even if they are not ignored by git
+    #[serde(default)]
+    pub exclude_glob: Vec<String>,
+
+    /// Text file with paths and globs (one per line) that should be removed from time machine backup.
+    /// Lines with glob characters are treated as `exclude_glob`, other lines as `exclude_paths`.
+    /// `-` reads standard input
+    #[serde(default)]
+    pub exclude_from: Option<String>,
+
+    /// Text file with additional `paths` (one per line). `-` reads standard input
+    #[serde(default)]
+    pub paths_from: Option<String>,
+
+    /// Entries of `paths_from` and `exclude_from` are separated by NUL instead of newlines,
+    /// as in `find -print0` output. Every entry is then a literal path
+    #[serde(default)]
+    pub null_separated: bool,
+
+    /// Should program report exclude paths that don't exist instead of failing
+    #[serde(default)]
+    pub missing_ok: bool,
+
+    /// Should globs, paths and regexes match case insensitively.
+    /// Detected from the volume of each path if not set
+    #[serde(default)]
+    pub case_insensitive: Option<bool>,
+
+    /// Match globs against full absolute paths instead of paths relative to each of `paths`.
+    /// Globs starting with `/` or `**` are always matched against absolute paths
+    #[serde(default)]
+    pub absolute_globs: bool,
+
+    /// Don't keep built-in list of secrets and local state in backup when they are
+    /// ignored by git: `.env`, keys and certificates, `credentials.json`, local databases.
+    /// Built-in list has the lowest precedence, any exclude rule overrides it
+    #[serde(default)]
+    pub disable_builtin_allowlist: bool,
+
+    /// Abort the run before excluding anything if it's going to add more new exclusions
+    #[serde(default)]
+    pub max_new_exclusions: Option<usize>,
+
+    /// Abort the run before excluding anything if new exclusions take more than
+    /// this percent of bytes of any of `paths`
+    #[serde(default)]
+    pub max_excluded_percent: Option<f64>,
+
+    /// Paths that can never be excluded, along with their contents and parents.
+    /// Repository roots are always protected. Runs that would exclude them are aborted
+    #[serde(default)]
+    pub protected_path: Vec<String>,
+
+    /// Record total size of new exclusions in run history.
+    /// Sizes of big directories take time to count
+    #[serde(default)]
+    pub history_bytes: bool,
+
+    /// Warn about probably hand-made files in items that are about to be excluded.
+    /// Always on in dry run
+    #[serde(default)]
+    pub warn_risky: bool,
+
+    /// Ordered list of rules. The last rule that matches a path wins.
+    /// Flat options above are applied before these rules
+    #[serde(default)]
+    pub rules: Vec<RuleConf>,
+
+    /// User-global .tmbliss file applied to every directory, with patterns
+    /// anchored to each of `paths`. Defaults to `~/.config/tmbliss/tmbliss`
+    #[serde(default)]
+    pub tmbliss_file: Option<String>,
+
+    /// Named sets of options that override top-level ones, e.g.
+    /// `{"work": {"paths": ["~/Work"], "allowlist_glob": [".env*"]}}`
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Value>,
+
+    /// Hostnames a profile is automatically selected on if no profile is given
+    #[serde(default)]
+    pub hosts: Vec<String>,
+
+    /// Configuration files merged over this one, relative to its directory
+    #[serde(default)]
+    pub include: Vec<String>,
+}
+
+/// Rule entry, e.g. `{"action": "allow", "glob": "**/.env"}`
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct RuleConf {
+    pub action: RuleAction,
+
+    #[serde(flatten)]
+    pub pattern: Pattern,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// Keep matched item in backup
+    Allow,
+    /// Remove matched item from backup even if it is not ignored by git
+    Exclude,
+    /// Don't process matched item and its children at all
+    Skip,
 }
 
-impl Default for Conf {
-    fn default() -> Self {
-        Self {
-            paths: Vec::new(),
-            allowlist_glob: Vec::new(),
-            allowlist_path: Vec::new(),
-            skip_glob: Vec::new(),
-            skip_path: Vec::new(),
-            dry_run: true,
-            skip_errors: true,
-            exclude_paths: Vec::new(),
+impl Display for RuleAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleAction::Allow => write!(f, "allow"),
+            RuleAction::Exclude => write!(f, "exclude"),
+            RuleAction::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+/// Pattern of a rule
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Pattern {
+    /// Glob matched the same way as `allowlist_glob`
+    Glob(String),
+    /// Path of the item or its ancestor
+    Path(String),
+    /// Regular expression matched against the full path of an item
+    Regex(String),
+}
+
+/// Configuration file format
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ConfFormat {
+    Json,
+    /// JSON with comments and trailing commas (JSONC, JSON5)
+    Json5,
+    Toml,
+    Yaml,
+}
+
+impl ConfFormat {
+    /// Detects format from the file extension
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("jsonc" | "json5") => ConfFormat::Json5,
+            Some("toml") => ConfFormat::Toml,
+            Some("yaml" | "yml") => ConfFormat::Yaml,
+            _ => ConfFormat::Json,
+        }
+    }
+
+    /// Deserializes content of the format, errors report line and column of the problem
+    pub fn deserialize<T: DeserializeOwned>(&self, content: &str) -> Result<T> {
+        match self {
+            ConfFormat::Json => Ok(serde_json::from_str(content)?),
+            ConfFormat::Json5 => json5::from_str(content).map_err(|err| match err {
+                json5::Error::Message {
+                    msg,
+                    location: Some(location),
+                } => anyhow!(
+                    "{} at line {} column {}",
+                    msg,
+                    location.line,
+                    location.column
+                ),
+                json5::Error::Message {
+                    msg,
+                    location: None,
+                } => anyhow!(msg),
+            }),
+            ConfFormat::Toml => toml::from_str(content).map_err(|err| {
+                let message = err.message().to_string();
+                match err.span() {
+                    Some(span) => {
+                        let before = &content[..span.start];
+                        let line = before.matches('\n').count() + 1;
+                        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+                        let column = before[line_start..].chars().count() + 1;
+                        anyhow!("{} at line {} column {}", message, line, column)
+                    }
+                    None => anyhow!(message),
+                }
+            }),
+            ConfFormat::Yaml => Ok(serde_norway::from_str(content)?),
+        }
+    }
+
+    /// Serializes value in the format. JSON5 is written as plain JSON
+    pub fn serialize<T: Serialize>(&self, value: &T) -> Result<String> {
+        match self {
+            ConfFormat::Json | ConfFormat::Json5 => Ok(serde_json::to_string_pretty(value)? + "\n"),
+            ConfFormat::Toml => Ok(toml::to_string_pretty(value)?),
+            ConfFormat::Yaml => Ok(serde_norway::to_string(value)?),
         }
     }
 }
 
 impl Conf {
     pub fn parse(path: &str) -> Result<Self> {
-        let file =
-            File::open(path).with_context(|| format!("Cannot open configuration at {}", path))?;
-        let reader = BufReader::new(file);
-        serde_json::from_reader(reader)
-            .with_context(|| format!("Cannot create reader for path {}", path))
+        Self::parse_as(path, None)
+    }
+
+    /// Parses configuration in the given format, or in the format
+    /// of the file extension. Files without known extension are parsed as JSON
+    pub fn parse_as(path: &str, format: Option<ConfFormat>) -> Result<Self> {
+        let format = format.unwrap_or_else(|| ConfFormat::from_path(Path::new(path)));
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Cannot open configuration at {}", path))?;
+        Self::parse_str(&content, format)
+            .with_context(|| format!("Cannot parse configuration at {}", path))?
+            .expand_paths(Path::new(path).parent())
+    }
+
+    /// Expands `~`, environment variables and globs in path options,
+    /// resolving relative paths against the base directory
+    pub fn expand_paths(self, base: Option<&Path>) -> Result<Self> {
+        let Value::Object(mut options) = serde_json::to_value(self)? else {
+            bail!("Configuration is not a map");
+        };
+        expand::expand_options(&mut options, base)?;
+        Ok(serde_json::from_value(Value::Object(options))?)
+    }
+
+    /// Parses configuration, errors report line and column of the problem.
+    /// Configuration of older version is migrated to the current one
+    pub fn parse_str(content: &str, format: ConfFormat) -> Result<Self> {
+        let Value::Object(mut options) = format.deserialize(content)? else {
+            bail!("Configuration is not a map");
+        };
+        if migrate::migrate(&mut options)?.is_empty() {
+            // Deserialize content itself to keep location of invalid options in errors
+            return format.deserialize(content);
+        }
+        Ok(serde_json::from_value(Value::Object(options))?)
+    }
+
+    /// Returns configuration of the profile: top-level options overridden by
+    /// options of the profile. `hosts` and `include` aren't inherited,
+    /// they only apply to the profile that declares them
+    pub fn profile(&self, name: &str) -> Result<Self> {
+        let Some(Value::Object(profile)) = self.profiles.get(name) else {
+            bail!("Unknown profile {}", name);
+        };
+        let mut value = serde_json::to_value(Self {
+            profiles: BTreeMap::new(),
+            hosts: vec![],
+            include: vec![],
+            ..self.clone()
+        })?;
+        let options = value
+            .as_object_mut()
+            .context("Configuration is not a map")?;
+        for (key, option) in profile {
+            if key == "profiles" {
+                bail!("Profile {} can't declare nested profiles", name);
+            }
+            options.insert(key.clone(), option.clone());
+        }
+        serde_json::from_value(value).with_context(|| format!("Invalid profile {}", name))
+    }
+
+    /// Resolves configurations to run with their profile names: the given profiles,
+    /// otherwise profiles which `hosts` contain current hostname, otherwise
+    /// the top-level configuration
+    pub fn resolve(self, names: &[String]) -> Result<Vec<(Option<String>, Self)>> {
+        if !names.is_empty() {
+            return names
+                .iter()
+                .map(|name| Ok((Some(name.clone()), self.profile(name)?)))
+                .collect();
+        }
+
+        let hostname = hostname::get()?.to_string_lossy().to_lowercase();
+        let mut selected = vec![];
+        for name in self.profiles.keys() {
+            let profile = self.profile(name)?;
+            if profile
+                .hosts
+                .iter()
+                .any(|host| Self::is_host(host, &hostname))
+            {
+                selected.push((Some(name.clone()), profile));
+            }
+        }
+        if selected.is_empty() {
+            return Ok(vec![(None, self)]);
+        }
+        Ok(selected)
+    }
+
+    /// Matches hostname, host without domain matches any domain (`laptop` matches `laptop.local`)
+    fn is_host(host: &str, hostname: &str) -> bool {
+        let host = host.to_lowercase();
+        hostname == host || hostname.split('.').next() == Some(host.as_str())
+    }
+
+    /// Moves entries of `paths_from` file to `paths`, and entries of `exclude_from` file
+    /// to `exclude_paths` and `exclude_glob`. An entry with glob characters is a glob
+    /// only if no file exists at its literal path, so names like `photo[1].jpg` stay paths
+    pub fn read_lists(&mut self) -> Result<()> {
+        if self.paths_from.as_deref() == Some("-") && self.exclude_from.as_deref() == Some("-") {
+            anyhow::bail!("Only one of paths_from and exclude_from can read standard input");
+        }
+        if let Some(path) = self.paths_from.take() {
+            let entries = Self::read_list(&path, self.null_separated)
+                .with_context(|| format!("Cannot read paths file at {}", path))?;
+            self.paths.extend(entries);
+        }
+        if let Some(path) = self.exclude_from.take() {
+            let entries = Self::read_list(&path, self.null_separated)
+                .with_context(|| format!("Cannot read exclude file at {}", path))?;
+            for entry in entries {
+                let is_glob = !self.null_separated
+                    && entry.contains(['*', '?', '[', '{'])
+                    && Path::new(&entry).symlink_metadata().is_err();
+                if is_glob {
+                    self.exclude_glob.push(entry);
+                } else {
+                    self.exclude_paths.push(entry);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads entries of a list file, `-` reads standard input. Newline separated
+    /// lists skip blank lines and `#` comments, NUL separated lists are taken as is
+    fn read_list(path: &str, null_separated: bool) -> Result<Vec<String>> {
+        let mut content = vec![];
+        if path == "-" {
+            io::stdin().lock().read_to_end(&mut content)?;
+        } else {
+            File::open(path)?.read_to_end(&mut content)?;
+        }
+        let content = String::from_utf8(content)?;
+
+        if null_separated {
+            return Ok(content
+                .split('\0')
+                .filter(|entry| !entry.is_empty())
+                .map(String::from)
+                .collect());
+        }
+        Ok(content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use test_case::test_case;
+
+    use super::ConfFormat;
+
     #[test]
     fn it_parses_config() {
         let conf = super::Conf::parse("./test_assets/test_config.json").unwrap();
@@ -69,6 +440,245 @@ mod tests {
         assert!(conf.dry_run);
     }
 
+    #[test_case("./test_assets/test_config.toml" ; "toml")]
+    #[test_case("./test_assets/test_config.yaml" ; "yaml")]
+    #[test_case("./test_assets/test_config.jsonc" ; "jsonc")]
+    fn it_parses_config_formats(path: &str) {
+        let conf = super::Conf::parse(path).unwrap();
+
+        assert_eq!(conf.paths, ["./test_assets/test_dir"]);
+        assert_eq!(conf.allowlist_glob, ["**/.env", "**/.env.*"]);
+        assert!(conf.dry_run);
+    }
+
+    #[test_case(ConfFormat::Json, "{\"paths\": [],\n  \"dry_run\": 1}" ; "json")]
+    #[test_case(ConfFormat::Json5, "{paths: [],\n  dry_run: tru}" ; "json5")]
+    #[test_case(ConfFormat::Toml, "paths = []\ndry_run = 1\n" ; "toml")]
+    #[test_case(ConfFormat::Yaml, "paths: []\ndry_run: 1\n" ; "yaml")]
+    fn it_reports_error_location(format: ConfFormat, content: &str) {
+        let err = super::Conf::parse_str(content, format).err().unwrap();
+
+        assert!(err.to_string().contains("line 2 column"), "{}", err);
+    }
+
+    #[test]
+    fn it_counts_toml_error_column_in_characters() {
+        let content = "paths = []\nskip_glob = [\"ü\", 1]\n";
+        let err = super::Conf::parse_str(content, ConfFormat::Toml)
+            .err()
+            .unwrap();
+
+        assert!(err.to_string().ends_with("at line 2 column 19"), "{}", err);
+    }
+
+    #[test]
+    fn it_defaults_to_serde_defaults() {
+        let parsed = super::Conf::parse_str("{\"paths\": []}", ConfFormat::Json).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(parsed).unwrap(),
+            serde_json::to_value(super::Conf::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_checks_version() {
+        let conf = super::Conf::parse_str(
+            "{\"paths\": [], \"exclude_paths\": [\"/big\"]}",
+            ConfFormat::Json,
+        )
+        .unwrap();
+        assert_eq!(conf.exclude_paths, ["/big"]);
+        assert_eq!(conf.version, None);
+
+        let newer = format!(
+            "{{\"paths\": [], \"version\": {}}}",
+            crate::constants::CONF_VERSION + 1
+        );
+        assert!(super::Conf::parse_str(&newer, ConfFormat::Json).is_err());
+        assert!(super::Conf::parse_str(
+            "{\"paths\": [], \"version\": 4294967296}",
+            ConfFormat::Json
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn it_generates_schema() {
+        let schema = serde_json::to_value(schemars::schema_for!(super::Conf)).unwrap();
+        let properties = &schema["properties"];
+
+        assert_eq!(schema["required"], serde_json::json!(["paths"]));
+        assert_eq!(
+            properties["exclude_paths"]["description"],
+            "Paths that should be removed from time machine backup"
+        );
+        assert_eq!(properties["dry_run"]["default"], false);
+    }
+
+    #[test_case(ConfFormat::Json ; "json")]
+    #[test_case(ConfFormat::Json5 ; "json5")]
+    #[test_case(ConfFormat::Toml ; "toml")]
+    #[test_case(ConfFormat::Yaml ; "yaml")]
+    fn it_serializes_config(format: ConfFormat) {
+        let options = serde_json::json!({
+            "paths": ["/Dev"],
+            "dry_run": true,
+            "rules": [{"action": "skip", "glob": "vendor"}],
+            "profiles": {"work": {"paths": ["/Work"]}},
+            "version": 2,
+        });
+
+        let content = format.serialize(&options).unwrap();
+        let parsed: serde_json::Value = format.deserialize(&content).unwrap();
+        assert_eq!(parsed, options);
+    }
+
+    #[test]
+    fn it_parses_rules() {
+        let conf = super::Conf::parse("./test_assets/test_config_rules.json").unwrap();
+
+        assert_eq!(
+            conf.rules,
+            [
+                super::RuleConf {
+                    action: super::RuleAction::Allow,
+                    pattern: super::Pattern::Glob("**/.env*".to_string()),
+                },
+                super::RuleConf {
+                    action: super::RuleAction::Exclude,
+                    pattern: super::Pattern::Regex("/\\.env\\.example$".to_string()),
+                },
+                super::RuleConf {
+                    action: super::RuleAction::Skip,
+                    pattern: super::Pattern::Path("./test_assets/test_dir/vendor".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reads_exclude_from_file() {
+        let mut conf = super::Conf {
+            exclude_paths: vec!["./a".to_string()],
+            exclude_from: Some("./test_assets/exclude_from.txt".to_string()),
+            ..super::Conf::default()
+        };
+        conf.read_lists().unwrap();
+
+        assert_eq!(conf.exclude_paths, ["./a", "./datasets/large.bin", "./vm"]);
+        assert_eq!(conf.exclude_glob, ["**/*.iso", "recordings/*.mov"]);
+        assert!(conf.exclude_from.is_none());
+    }
+
+    #[test]
+    fn it_reads_existing_paths_with_glob_characters_as_paths() {
+        let dir = crate::test_utils::TestDir::new();
+        let existing = dir.join("photo[1].jpg").to_string_lossy().to_string();
+        let missing = dir.join("photo[2].jpg").to_string_lossy().to_string();
+        std::fs::write(&existing, "").unwrap();
+        let list = dir.join("list");
+        std::fs::write(&list, format!("{}\n{}\n", existing, missing)).unwrap();
+        let mut conf = super::Conf {
+            exclude_from: Some(list.to_string_lossy().to_string()),
+            ..super::Conf::default()
+        };
+        conf.read_lists().unwrap();
+
+        assert_eq!(conf.exclude_paths, [existing]);
+        assert_eq!(conf.exclude_glob, [missing]);
+    }
+
+    #[test]
+    fn it_reads_null_separated_lists() {
+        let dir = crate::test_utils::TestDir::new();
+        let list = dir.join("list");
+        std::fs::write(&list, "./b c\0./*.d\0#e\0").unwrap();
+        let list = list.to_string_lossy().to_string();
+        let mut conf = super::Conf {
+            paths: vec!["./a".to_string()],
+            paths_from: Some(list.clone()),
+            exclude_from: Some(list),
+            null_separated: true,
+            ..super::Conf::default()
+        };
+        conf.read_lists().unwrap();
+
+        assert_eq!(conf.paths, ["./a", "./b c", "./*.d", "#e"]);
+        assert_eq!(conf.exclude_paths, ["./b c", "./*.d", "#e"]);
+        assert!(conf.exclude_glob.is_empty());
+    }
+
+    #[test]
+    fn it_fails_if_both_lists_read_stdin() {
+        let mut conf = super::Conf {
+            paths_from: Some("-".to_string()),
+            exclude_from: Some("-".to_string()),
+            ..super::Conf::default()
+        };
+
+        assert!(conf.read_lists().is_err());
+    }
+
+    #[test]
+    fn it_resolves_profiles() {
+        let conf = super::Conf::parse("./test_assets/test_config_profiles.json").unwrap();
+
+        let profiles = conf
+            .clone()
+            .resolve(&["dev".to_string(), "work".to_string()])
+            .unwrap();
+        let (name, dev) = &profiles[0];
+        assert_eq!(name.as_deref(), Some("dev"));
+        assert_eq!(dev.paths, ["./test_assets/test_dir"]);
+        assert_eq!(dev.exclude_glob, ["**/*.iso"]);
+        assert_eq!(dev.skip_glob, ["node_modules"]);
+        assert!(!dev.dry_run);
+        let (name, work) = &profiles[1];
+        assert_eq!(name.as_deref(), Some("work"));
+        assert_eq!(work.allowlist_glob, [".env*"]);
+        assert_eq!(work.skip_glob, ["node_modules"]);
+        assert!(work.dry_run);
+
+        assert!(conf.profile("missing").is_err());
+    }
+
+    #[test]
+    fn it_selects_profiles_by_hostname() {
+        let hostname = hostname::get().unwrap().to_string_lossy().to_string();
+        let mut conf = super::Conf::parse("./test_assets/test_config_profiles.json").unwrap();
+
+        let resolved = conf.clone().resolve(&[]).unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert!(resolved[0].0.is_none());
+
+        conf.profiles
+            .get_mut("work")
+            .unwrap()
+            .as_object_mut()
+            .unwrap()
+            .insert("hosts".to_string(), serde_json::json!([hostname]));
+        let resolved = conf.resolve(&[]).unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].0.as_deref(), Some("work"));
+    }
+
+    #[test]
+    fn it_does_not_inherit_hosts_in_profiles() {
+        let hostname = hostname::get().unwrap().to_string_lossy().to_string();
+        let mut conf = super::Conf::parse("./test_assets/test_config_profiles.json").unwrap();
+        conf.hosts = vec![hostname];
+        conf.include = vec!["./other.json".to_string()];
+
+        let dev = conf.profile("dev").unwrap();
+        assert!(dev.hosts.is_empty());
+        assert!(dev.include.is_empty());
+
+        let resolved = conf.resolve(&[]).unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert!(resolved[0].0.is_none());
+    }
+
     #[test]
     fn it_fails_if_no_paths_provided() {
         let conf = super::Conf::parse("./test_assets/test_config_no_paths.json");
@@ -0,0 +1,379 @@
+use std::{collections::HashSet, fmt::Display, path::Path};
+
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use crate::conf::{Conf, Pattern, RuleAction, RuleConf};
+use crate::conf_layers::ConfLayers;
+use crate::matcher::Matcher;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Problem found in configuration
+#[derive(Debug, PartialEq)]
+pub struct Issue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Validates configuration layers: unknown options, glob and regex syntax,
+/// paths existence, duplicate and contradictory entries.
+/// Every profile is checked as well
+pub fn check(layers: &ConfLayers) -> Vec<Issue> {
+    let mut issues = vec![];
+    let known = known_options();
+
+    for layer in &layers.layers {
+        for change in &layer.migrated {
+            issues.push(warning(
+                &layer.source,
+                format!("{}, run `tmbliss conf migrate`", change),
+            ));
+        }
+        check_options(&layer.options, &known, &layer.source, &mut issues);
+        if let Some(Value::Object(profiles)) = layer.options.get("profiles") {
+            for (name, profile) in profiles {
+                let source = format!("{}: profile {}", layer.source, name);
+                match profile {
+                    Value::Object(profile) => check_options(profile, &known, &source, &mut issues),
+                    _ => issues.push(error(&source, "profile is not a map".to_string())),
+                }
+            }
+        }
+    }
+
+    let conf = match layers.resolve() {
+        Ok(conf) => conf,
+        Err(err) => {
+            issues.push(error("configuration", format!("{:#}", err)));
+            return issues;
+        }
+    };
+    check_conf(&conf, "configuration", &mut issues);
+    for name in conf.profiles.keys() {
+        let source = format!("profile {}", name);
+        match conf.profile(name) {
+            Ok(profile) => check_conf(&profile, &source, &mut issues),
+            Err(err) => issues.push(error(&source, format!("{:#}", err))),
+        }
+    }
+
+    issues
+}
+
+/// Option names, derived from serialized default configuration
+fn known_options() -> Vec<String> {
+    match serde_json::to_value(Conf::default()) {
+        Ok(Value::Object(options)) => options.keys().cloned().collect(),
+        _ => vec![],
+    }
+}
+
+fn check_options(
+    options: &Map<String, Value>,
+    known: &[String],
+    source: &str,
+    issues: &mut Vec<Issue>,
+) {
+    for key in options.keys() {
+        // `$schema` points editors to the JSON Schema of configuration
+        if known.contains(key) || key == "$schema" {
+            continue;
+        }
+        let suggestion = known
+            .iter()
+            .map(|option| (distance(key, option), option))
+            .filter(|(distance, _)| *distance <= 3)
+            .min();
+        let message = match suggestion {
+            Some((_, option)) => format!("unknown option `{}`, did you mean `{}`?", key, option),
+            None => format!("unknown option `{}`", key),
+        };
+        issues.push(error(source, message));
+    }
+}
+
+fn check_conf(conf: &Conf, source: &str, issues: &mut Vec<Issue>) {
+    if conf.paths.is_empty() && conf.paths_from.is_none() && conf.profiles.is_empty() {
+        issues.push(error(source, "`paths` is empty".to_string()));
+    }
+
+    for (option, globs) in [
+        ("allowlist_glob", &conf.allowlist_glob),
+        ("skip_glob", &conf.skip_glob),
+        ("exclude_glob", &conf.exclude_glob),
+    ] {
+        check_duplicates(option, globs, source, issues);
+        for item in globs {
+            if let Err(err) = Matcher::validate_glob(item) {
+                issues.push(error(
+                    source,
+                    format!("invalid {} `{}`: {}", option, item, err),
+                ));
+            }
+        }
+    }
+    for (option, regexes) in [
+        ("allowlist_regex", &conf.allowlist_regex),
+        ("skip_regex", &conf.skip_regex),
+        ("exclude_regex", &conf.exclude_regex),
+    ] {
+        check_duplicates(option, regexes, source, issues);
+        for item in regexes {
+            if let Err(err) = Regex::new(item) {
+                issues.push(error(
+                    source,
+                    format!("invalid {} `{}`: {}", option, item, err),
+                ));
+            }
+        }
+    }
+    for (index, rule) in conf.rules.iter().enumerate() {
+        let message = match &rule.pattern {
+            Pattern::Glob(item) => Matcher::validate_glob(item)
+                .err()
+                .map(|err| format!("invalid glob `{}`: {}", item, err)),
+            Pattern::Regex(item) => Regex::new(item).err().map(|err| err.to_string()),
+            Pattern::Path(item) => {
+                (!Path::new(item).exists()).then(|| format!("path {} doesn't exist", item))
+            }
+        };
+        if let Some(message) = message {
+            issues.push(error(
+                source,
+                format!("invalid rules[{}]: {}", index, message),
+            ));
+        }
+    }
+
+    check_duplicates("paths", &conf.paths, source, issues);
+    for item in &conf.paths {
+        if !Path::new(item).is_dir() {
+            issues.push(error(
+                source,
+                format!("paths entry {} is not a directory", item),
+            ));
+        }
+    }
+    for (option, paths) in [
+        ("allowlist_path", &conf.allowlist_path),
+        ("skip_path", &conf.skip_path),
+        ("exclude_paths", &conf.exclude_paths),
+    ] {
+        check_duplicates(option, paths, source, issues);
+        for item in paths {
+            if !Path::new(item).exists() {
+                if option != "exclude_paths" || !conf.missing_ok {
+                    issues.push(error(
+                        source,
+                        format!("{} entry {} doesn't exist", option, item),
+                    ));
+                }
+                continue;
+            }
+            let item_path = Path::new(item).canonicalize().unwrap_or_default();
+            let inside = conf.paths.iter().any(|root| {
+                Path::new(root)
+                    .canonicalize()
+                    .is_ok_and(|root| item_path.starts_with(root))
+            });
+            if option != "exclude_paths" && !inside {
+                issues.push(warning(
+                    source,
+                    format!(
+                        "{} entry {} is outside of `paths`, it never matches",
+                        option, item
+                    ),
+                ));
+            }
+        }
+    }
+    for (option, file) in [
+        ("exclude_from", &conf.exclude_from),
+        ("paths_from", &conf.paths_from),
+        ("tmbliss_file", &conf.tmbliss_file),
+    ] {
+        if let Some(file) = file.as_deref().filter(|file| *file != "-") {
+            if !Path::new(file).is_file() {
+                issues.push(error(
+                    source,
+                    format!("{} file {} doesn't exist", option, file),
+                ));
+            }
+        }
+    }
+
+    for kind in ["glob", "path", "regex"] {
+        let (allow, skip, exclude) = match kind {
+            "glob" => (&conf.allowlist_glob, &conf.skip_glob, &conf.exclude_glob),
+            "path" => (&conf.allowlist_path, &conf.skip_path, &conf.exclude_paths),
+            _ => (&conf.allowlist_regex, &conf.skip_regex, &conf.exclude_regex),
+        };
+        let allow = entries(allow, &conf.rules, RuleAction::Allow, kind);
+        let skip = entries(skip, &conf.rules, RuleAction::Skip, kind);
+        let exclude = entries(exclude, &conf.rules, RuleAction::Exclude, kind);
+        for (first, first_name, second, second_name) in [
+            (&allow, "allowed", &skip, "skipped"),
+            (&allow, "allowed", &exclude, "excluded"),
+            (&skip, "skipped", &exclude, "excluded"),
+        ] {
+            let mut both = first.intersection(second).collect::<Vec<_>>();
+            both.sort();
+            for item in both {
+                issues.push(warning(
+                    source,
+                    format!(
+                        "{} `{}` is both {} and {}",
+                        kind, item, first_name, second_name
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+/// Entries of a flat option together with patterns of the same kind and action from `rules`
+fn entries<'a>(
+    flat: &'a [String],
+    rules: &'a [RuleConf],
+    action: RuleAction,
+    kind: &str,
+) -> HashSet<&'a String> {
+    let patterns = rules
+        .iter()
+        .filter(|rule| rule.action == action)
+        .filter_map(|rule| match (&rule.pattern, kind) {
+            (Pattern::Glob(item), "glob") => Some(item),
+            (Pattern::Path(item), "path") => Some(item),
+            (Pattern::Regex(item), "regex") => Some(item),
+            _ => None,
+        });
+    flat.iter().chain(patterns).collect()
+}
+
+fn check_duplicates(option: &str, items: &[String], source: &str, issues: &mut Vec<Issue>) {
+    let mut seen = HashSet::new();
+    for item in items {
+        if !seen.insert(item) {
+            issues.push(warning(
+                source,
+                format!("duplicate {} entry `{}`", option, item),
+            ));
+        }
+    }
+}
+
+fn error(source: &str, message: String) -> Issue {
+    Issue {
+        severity: Severity::Error,
+        message: format!("{}: {}", source, message),
+    }
+}
+
+fn warning(source: &str, message: String) -> Issue {
+    Issue {
+        severity: Severity::Warning,
+        message: format!("{}: {}", source, message),
+    }
+}
+
+/// Levenshtein distance between option names
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(row[j + 1])
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_utils::TestDir;
+
+    use super::*;
+
+    #[test]
+    fn it_reports_issues() {
+        let dir = TestDir::new();
+        fs::create_dir_all(dir.join("root/vendor")).unwrap();
+        fs::write(
+            dir.join("conf.json"),
+            r#"{
+                "paths": ["root"],
+                "allowlist_globs": [".env"],
+                "skip_glob": ["a[b", "node_modules", "node_modules"],
+                "exclude_regex": ["("],
+                "skip_path": ["root/vendor", "missing"],
+                "exclude_paths": ["root/vendor"],
+                "profiles": {"work": {"dryrun": true}}
+            }"#,
+        )
+        .unwrap();
+        let layers = ConfLayers::load_from(None, Some(&dir.join("conf.json")), None, []).unwrap();
+        let root = dir.join("root");
+        let root = root.display();
+
+        let issues = check(&layers)
+            .into_iter()
+            .map(|issue| format!("{}: {}", issue.severity, issue.message))
+            .collect::<Vec<_>>();
+        let source = dir.join("conf.json").display().to_string();
+        assert_eq!(
+            issues,
+            [
+                format!("error: {}: unknown option `allowlist_globs`, did you mean `allowlist_glob`?", source),
+                format!("error: {}: profile work: unknown option `dryrun`, did you mean `dry_run`?", source),
+                "warning: configuration: duplicate skip_glob entry `node_modules`".to_string(),
+                "error: configuration: invalid skip_glob `a[b`: unclosed character class".to_string(),
+                "error: configuration: invalid exclude_regex `(`: regex parse error:\n    (\n    ^\nerror: unclosed group".to_string(),
+                format!("error: configuration: skip_path entry {}/missing doesn't exist", dir.path().display()),
+                format!("warning: configuration: path `{}/vendor` is both skipped and excluded", root),
+                "warning: profile work: duplicate skip_glob entry `node_modules`".to_string(),
+                "error: profile work: invalid skip_glob `a[b`: unclosed character class".to_string(),
+                "error: profile work: invalid exclude_regex `(`: regex parse error:\n    (\n    ^\nerror: unclosed group".to_string(),
+                format!("error: profile work: skip_path entry {}/missing doesn't exist", dir.path().display()),
+                format!("warning: profile work: path `{}/vendor` is both skipped and excluded", root),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_accepts_valid_configuration() {
+        let dir = TestDir::new();
+        fs::create_dir_all(dir.join("root")).unwrap();
+        fs::write(
+            dir.join("conf.toml"),
+            "paths = [\"root\"]\nallowlist_glob = [\".env*\"]\n",
+        )
+        .unwrap();
+        let layers = ConfLayers::load_from(None, Some(&dir.join("conf.toml")), None, []).unwrap();
+
+        assert_eq!(check(&layers), []);
+    }
+}
@@ -0,0 +1,310 @@
+use std::{
+    env, fs,
+    io::{BufRead, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+
+use crate::conf::{Conf, ConfFormat};
+use crate::constants::CONF_VERSION;
+
+/// Directories offered as roots, relative to home directory
+const CANDIDATE_ROOTS: [&str; 6] = ["Dev", "Developer", "Projects", "src", "code", "repos"];
+
+/// Options written to a starter configuration even if they are empty
+const STARTER_OPTIONS: [&str; 7] = [
+    "version",
+    "paths",
+    "allowlist_glob",
+    "skip_glob",
+    "exclude_glob",
+    "dry_run",
+    "skip_errors",
+];
+
+/// Common sets of git-ignored files that should be kept in backup
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Preset {
+    /// Local secrets and keys: .env files, certificates, private keys
+    Secrets,
+    /// Local databases: SQLite files
+    Databases,
+    /// Editor settings: .vscode, .idea
+    Editors,
+}
+
+impl Preset {
+    pub fn globs(&self) -> &'static [&'static str] {
+        match self {
+            Preset::Secrets => &["**/.env", "**/.env.*", "**/*.pem", "**/*.key"],
+            Preset::Databases => &[
+                "**/*.sqlite",
+                "**/*.sqlite3",
+                "**/*.db",
+                "**/*.db-wal",
+                "**/*.db-shm",
+            ],
+            Preset::Editors => &["**/.vscode", "**/.idea"],
+        }
+    }
+}
+
+/// Directories with personal files that starter configuration protects from exclusion
+const PROTECTED_PATHS: [&str; 2] = ["~/Documents", "~/Desktop"];
+
+/// New exclusions per run allowed by starter configuration
+const MAX_NEW_EXCLUSIONS: usize = 1000;
+
+/// Starter configuration with the given roots and allowlist presets.
+/// Starts in dry run mode, so nothing is changed until it's reviewed
+pub fn starter(paths: Vec<String>, presets: &[Preset]) -> Conf {
+    Conf {
+        version: Some(CONF_VERSION),
+        paths,
+        allowlist_glob: presets
+            .iter()
+            .flat_map(|preset| preset.globs())
+            .map(|glob| glob.to_string())
+            .collect(),
+        dry_run: true,
+        skip_errors: true,
+        max_new_exclusions: Some(MAX_NEW_EXCLUSIONS),
+        protected_path: PROTECTED_PATHS
+            .iter()
+            .map(|path| path.to_string())
+            .collect(),
+        ..Conf::default()
+    }
+}
+
+/// Asks which candidate roots and presets to use
+pub fn ask(
+    mut input: impl BufRead,
+    mut output: impl Write,
+    home: &Path,
+) -> Result<(Vec<String>, Vec<Preset>)> {
+    let mut paths = vec![];
+    for root in CANDIDATE_ROOTS {
+        if !home.join(root).is_dir() {
+            continue;
+        }
+        let path = format!("~/{}", root);
+        if confirm(&mut input, &mut output, &format!("Process {}?", path), true)? {
+            paths.push(path);
+        }
+    }
+    loop {
+        write!(output, "Other directory to process (empty to continue): ")?;
+        output.flush()?;
+        let answer = read_line(&mut input)?;
+        if answer.is_empty() {
+            break;
+        }
+        paths.push(answer);
+    }
+
+    let mut presets = vec![];
+    for preset in Preset::value_variants() {
+        let Some(value) = preset.to_possible_value() else {
+            continue;
+        };
+        let question = format!(
+            "Keep {} in backup ({})?",
+            value.get_name(),
+            preset.globs().join(", ")
+        );
+        if confirm(&mut input, &mut output, &question, true)? {
+            presets.push(*preset);
+        }
+    }
+    Ok((paths, presets))
+}
+
+fn confirm(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    question: &str,
+    default: bool,
+) -> Result<bool> {
+    loop {
+        let hint = if default { "Y/n" } else { "y/N" };
+        write!(output, "{} [{}] ", question, hint)?;
+        output.flush()?;
+        match read_line(input)?.to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => continue,
+        }
+    }
+}
+
+fn read_line(input: &mut impl BufRead) -> Result<String> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        bail!("Unexpected end of input");
+    }
+    Ok(line.trim().to_string())
+}
+
+/// Renders configuration of the current version with option descriptions as comments.
+/// Starter options are always written, other options only if they differ from defaults.
+/// JSON has no comments, so it's written without them
+pub fn render(conf: &Conf, format: ConfFormat) -> Result<String> {
+    let Value::Object(mut options) = serde_json::to_value(conf)? else {
+        bail!("Configuration is not a map");
+    };
+    options.insert("version".to_string(), Value::from(CONF_VERSION));
+    let Value::Object(defaults) = serde_json::to_value(Conf::default())? else {
+        bail!("Configuration is not a map");
+    };
+    let schema = serde_json::to_value(schemars::schema_for!(Conf))?;
+
+    let mut keys = STARTER_OPTIONS.to_vec();
+    keys.extend(
+        options.keys().map(String::as_str).filter(|key| {
+            !STARTER_OPTIONS.contains(key) && options.get(*key) != defaults.get(*key)
+        }),
+    );
+
+    let mut entries = vec![];
+    for key in keys {
+        let Some(option) = options.get(key).filter(|option| !option.is_null()) else {
+            continue;
+        };
+        let description = schema["properties"][key]["description"]
+            .as_str()
+            .unwrap_or_default();
+        let mut single = Map::new();
+        single.insert(key.to_string(), option.clone());
+        let content = match format {
+            ConfFormat::Json | ConfFormat::Json5 => {
+                let value = serde_json::to_string_pretty(option)?.replace('\n', "\n  ");
+                format!("\"{}\": {}", key, value)
+            }
+            _ => format.serialize(&single)?,
+        };
+        entries.push((description, content.trim_end().to_string()));
+    }
+
+    let mut out = String::new();
+    match format {
+        ConfFormat::Json | ConfFormat::Json5 => {
+            out.push_str("{\n");
+            for (index, (description, content)) in entries.iter().enumerate() {
+                if format == ConfFormat::Json5 {
+                    for line in description.lines() {
+                        out.push_str(&format!("  // {}\n", line));
+                    }
+                }
+                let separator = if index + 1 < entries.len() { "," } else { "" };
+                out.push_str(&format!("  {}{}\n", content, separator));
+            }
+            out.push_str("}\n");
+        }
+        ConfFormat::Toml | ConfFormat::Yaml => {
+            if format == ConfFormat::Toml {
+                // Tables go after plain values, otherwise values would belong to the table
+                entries.sort_by_key(|(_, content)| content.starts_with('['));
+            }
+            for (index, (description, content)) in entries.iter().enumerate() {
+                if index > 0 {
+                    out.push('\n');
+                }
+                for line in description.lines() {
+                    out.push_str(&format!("# {}\n", line));
+                }
+                out.push_str(content);
+                out.push('\n');
+            }
+        }
+    }
+
+    // Written configuration must be readable as is
+    Conf::parse_str(&out, format).context("Rendered configuration is invalid")?;
+    Ok(out)
+}
+
+/// Makes relative paths of configuration built from command line flags absolute,
+/// as paths in configuration file are relative to its directory
+pub fn absolute_paths(conf: Conf) -> Result<Conf> {
+    conf.expand_paths(Some(&env::current_dir()?))
+}
+
+/// Writes rendered configuration, format is detected from the file extension if not set
+pub fn save(conf: &Conf, path: &Path, format: Option<ConfFormat>, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        bail!("Configuration at {} already exists", path.display());
+    }
+    let content = render(conf, format.unwrap_or_else(|| ConfFormat::from_path(path)))?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Cannot create directory {}", dir.display()))?;
+    }
+    fs::write(path, content)
+        .with_context(|| format!("Cannot write configuration at {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use crate::test_utils::TestDir;
+
+    use super::*;
+
+    #[test_case(ConfFormat::Json, "conf.json" ; "json")]
+    #[test_case(ConfFormat::Json5, "conf.jsonc" ; "json5")]
+    #[test_case(ConfFormat::Toml, "conf.toml" ; "toml")]
+    #[test_case(ConfFormat::Yaml, "conf.yaml" ; "yaml")]
+    fn it_renders_starter_configuration(format: ConfFormat, name: &str) {
+        let dir = TestDir::new();
+        let mut conf = starter(vec!["./Dev".to_string()], &[Preset::Secrets]);
+        conf.skip_path = vec!["./Dev/vendor".to_string()];
+        let path = dir.join(name);
+
+        save(&conf, &path, None, false).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            content.contains("Globs for items that should be kept in backup"),
+            format != ConfFormat::Json
+        );
+        assert!(!content.contains("exclude_from"));
+
+        let parsed = Conf::parse(&path.to_string_lossy()).unwrap();
+        let dev = dir.join("Dev").to_string_lossy().into_owned();
+        assert_eq!(parsed.version, Some(CONF_VERSION));
+        assert_eq!(parsed.skip_path, [format!("{}/vendor", dev)]);
+        assert_eq!(parsed.paths, [dev]);
+        assert_eq!(parsed.allowlist_glob, Preset::Secrets.globs());
+        assert!(parsed.dry_run);
+        assert_eq!(parsed.max_new_exclusions, Some(MAX_NEW_EXCLUSIONS));
+        assert_eq!(parsed.protected_path.len(), PROTECTED_PATHS.len());
+
+        assert!(save(&conf, &path, None, false).is_err());
+        assert!(save(&conf, &path, None, true).is_ok());
+    }
+
+    #[test]
+    fn it_asks_for_roots_and_presets() {
+        let dir = TestDir::new();
+        fs::create_dir_all(dir.join("Dev")).unwrap();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        let mut output = vec![];
+
+        let (paths, presets) = ask(
+            "\nn\n/Work\n\nmaybe\ny\nn\n\n".as_bytes(),
+            &mut output,
+            dir.path(),
+        )
+        .unwrap();
+        assert_eq!(paths, ["~/Dev", "/Work"]);
+        assert_eq!(presets, [Preset::Secrets, Preset::Editors]);
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .starts_with("Process ~/Dev? [Y/n] Process ~/src? [Y/n] "));
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    collections::HashSet,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+
+use crate::conf::{Conf, ConfFormat};
+use crate::constants::{CONFIG_ENV, CONFIG_FILE, ENV_PREFIX, HOME_CONFIG_FILE, SYSTEM_CONFIG_DIR};
+use crate::dirs;
+use crate::expand;
+use crate::migrate;
+
+/// Extensions of discovered configuration files, in order of preference
+const EXTENSIONS: [&str; 6] = ["json", "jsonc", "json5", "toml", "yaml", "yml"];
+
+/// Options of a single configuration source
+pub struct Layer {
+    /// File path or `environment`
+    pub source: String,
+    pub options: Map<String, Value>,
+    /// Changes made to migrate options of an older version
+    pub migrated: Vec<String>,
+}
+
+/// Configuration merged from several sources. Later layers override options
+/// of earlier ones: system-wide file, user file, files they include,
+/// then environment variables
+pub struct ConfLayers {
+    pub layers: Vec<Layer>,
+}
+
+impl ConfLayers {
+    /// Loads all layers. The given file replaces discovered user configuration
+    pub fn load(path: Option<&Path>, format: Option<ConfFormat>) -> Result<Self> {
+        let system = Self::find_config(Path::new(SYSTEM_CONFIG_DIR));
+        let user = match path {
+            Some(path) => Some(path.to_path_buf()),
+            None => Self::discover(),
+        };
+        Self::load_from(system.as_deref(), user.as_deref(), format, env::vars())
+    }
+
+    /// Loads layers from the given files and environment variables.
+    /// Format applies to the user file only, other files are detected by extension
+    pub fn load_from(
+        system: Option<&Path>,
+        user: Option<&Path>,
+        format: Option<ConfFormat>,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self> {
+        let mut layers = Self { layers: vec![] };
+        let mut visited = HashSet::new();
+        if let Some(system) = system {
+            layers.push_file(system, None, &mut visited)?;
+        }
+        if let Some(user) = user {
+            layers.push_file(user, format, &mut visited)?;
+        }
+        layers.push_env(vars)?;
+
+        if layers.layers.is_empty() {
+            bail!(
+                "Configuration not found. Pass --path, set {} or create ~/{}",
+                CONFIG_ENV,
+                HOME_CONFIG_FILE
+            );
+        }
+        Ok(layers)
+    }
+
+    /// Finds user configuration file: `$TMBLISS_CONFIG`, then
+    /// `$XDG_CONFIG_HOME/tmbliss/config.*`, then `~/.tmbliss.json`
+    pub fn discover() -> Option<PathBuf> {
+        if let Some(path) = env::var_os(CONFIG_ENV).filter(|path| !path.is_empty()) {
+            return Some(PathBuf::from(path));
+        }
+        dirs::config_dir()
+            .and_then(|dir| Self::find_config(&dir))
+            .or_else(|| {
+                dirs::home_dir()
+                    .map(|home| home.join(HOME_CONFIG_FILE))
+                    .filter(|path| path.is_file())
+            })
+    }
+
+    /// Merges layers into configuration
+    pub fn resolve(&self) -> Result<Conf> {
+        let mut merged = Map::new();
+        for layer in &self.layers {
+            for (key, option) in &layer.options {
+                if key != "include" {
+                    merged.insert(key.clone(), option.clone());
+                }
+            }
+        }
+        serde_json::from_value(Value::Object(merged)).map_err(|err| {
+            // Point to the layer with invalid option if there is one
+            let source = self.layers.iter().find(|layer| {
+                let mut options = layer.options.clone();
+                options
+                    .entry("paths")
+                    .or_insert_with(|| Value::Array(vec![]));
+                serde_json::from_value::<Conf>(Value::Object(options)).is_err()
+            });
+            match source {
+                Some(layer) => anyhow::Error::new(err)
+                    .context(format!("Invalid configuration at {}", layer.source)),
+                None => anyhow::Error::new(err).context("Invalid configuration"),
+            }
+        })
+    }
+
+    fn find_config(dir: &Path) -> Option<PathBuf> {
+        EXTENSIONS
+            .iter()
+            .map(|extension| dir.join(CONFIG_FILE).with_extension(extension))
+            .find(|path| path.is_file())
+    }
+
+    /// Adds configuration file followed by files it includes
+    fn push_file(
+        &mut self,
+        path: &Path,
+        format: Option<ConfFormat>,
+        visited: &mut HashSet<PathBuf>,
+    ) -> Result<()> {
+        let canonical = path
+            .canonicalize()
+            .with_context(|| format!("Cannot open configuration at {}", path.display()))?;
+        if !visited.insert(canonical) {
+            bail!("Configuration at {} is included twice", path.display());
+        }
+        let format = format.unwrap_or_else(|| ConfFormat::from_path(path));
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Cannot open configuration at {}", path.display()))?;
+        let Value::Object(mut options) = format
+            .deserialize(&content)
+            .with_context(|| format!("Cannot parse configuration at {}", path.display()))?
+        else {
+            bail!("Configuration at {} is not a map", path.display());
+        };
+
+        let migrated = migrate::migrate(&mut options)
+            .with_context(|| format!("Cannot migrate configuration at {}", path.display()))?;
+        expand::expand_options(&mut options, path.parent())
+            .with_context(|| format!("Cannot expand paths at {}", path.display()))?;
+
+        let include = options.get("include").cloned();
+        self.layers.push(Layer {
+            source: path.display().to_string(),
+            options,
+            migrated,
+        });
+        if let Some(include) = include {
+            let include: Vec<String> = serde_json::from_value(include)
+                .with_context(|| format!("Invalid include at {}", path.display()))?;
+            let dir = path.parent().unwrap_or(Path::new(""));
+            for item in include {
+                self.push_file(&dir.join(item), None, visited)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds options set by `TMBLISS_<OPTION>` environment variables, e.g. `TMBLISS_DRY_RUN=true`.
+    /// List options are `:` separated unless given as JSON array,
+    /// other values are parsed as JSON or taken as strings
+    fn push_env(&mut self, vars: impl IntoIterator<Item = (String, String)>) -> Result<()> {
+        let Value::Object(defaults) = serde_json::to_value(Conf::default())? else {
+            bail!("Configuration is not a map");
+        };
+        let mut options = Map::new();
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX).map(str::to_lowercase) else {
+                continue;
+            };
+            let Some(default) = defaults.get(&key) else {
+                continue;
+            };
+            let option = match default {
+                Value::Array(_) if !value.trim_start().starts_with('[') => Value::Array(
+                    value
+                        .split(':')
+                        .filter(|item| !item.is_empty())
+                        .map(|item| Value::String(item.to_string()))
+                        .collect(),
+                ),
+                Value::Bool(_) => match value.to_lowercase().as_str() {
+                    "true" | "yes" | "on" | "1" => Value::Bool(true),
+                    "false" | "no" | "off" | "0" => Value::Bool(false),
+                    _ => bail!("Invalid boolean {}={}", name, value),
+                },
+                _ => serde_json::from_str(&value).unwrap_or(Value::String(value)),
+            };
+            options.insert(key, option);
+        }
+
+        if !options.is_empty() {
+            expand::expand_options(&mut options, None)
+                .context("Cannot expand paths of environment variables")?;
+            self.layers.push(Layer {
+                source: "environment".to_string(),
+                options,
+                migrated: vec![],
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::TestDir;
+
+    use super::*;
+
+    #[test]
+    fn it_merges_layers() {
+        let dir = TestDir::new();
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        fs::write(
+            dir.join("system.toml"),
+            "paths = [\"/system\"]\nallowlist_glob = [\".env\"]\nskip_errors = true\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("user.json"),
+            r#"{"paths": ["/user"], "include": ["conf.d/team.yaml"]}"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("conf.d/team.yaml"),
+            "skip_glob:\n  - node_modules\n",
+        )
+        .unwrap();
+
+        let layers = ConfLayers::load_from(
+            Some(&dir.join("system.toml")),
+            Some(&dir.join("user.json")),
+            None,
+            [
+                ("TMBLISS_DRY_RUN".to_string(), "false".to_string()),
+                (
+                    "TMBLISS_EXCLUDE_GLOB".to_string(),
+                    "*.iso:*.dmg".to_string(),
+                ),
+                ("TMBLISS_CONFIG".to_string(), "ignored".to_string()),
+                ("HOME".to_string(), "ignored".to_string()),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            layers
+                .layers
+                .iter()
+                .map(|layer| layer.source.clone())
+                .collect::<Vec<_>>(),
+            [
+                dir.join("system.toml").display().to_string(),
+                dir.join("user.json").display().to_string(),
+                dir.join("conf.d/team.yaml").display().to_string(),
+                "environment".to_string(),
+            ]
+        );
+
+        let conf = layers.resolve().unwrap();
+        assert_eq!(conf.paths, ["/user"]);
+        assert_eq!(conf.allowlist_glob, [".env"]);
+        assert_eq!(conf.skip_glob, ["node_modules"]);
+        assert_eq!(conf.exclude_glob, ["*.iso", "*.dmg"]);
+        assert!(conf.skip_errors);
+        assert!(!conf.dry_run);
+    }
+
+    #[test]
+    fn it_reports_invalid_layer() {
+        let dir = TestDir::new();
+        fs::write(dir.join("user.json"), r#"{"paths": ["/user"]}"#).unwrap();
+
+        let layers = ConfLayers::load_from(
+            None,
+            Some(&dir.join("user.json")),
+            None,
+            [("TMBLISS_CASE_INSENSITIVE".to_string(), "maybe".to_string())],
+        )
+        .unwrap();
+        let err = layers.resolve().err().unwrap();
+        assert_eq!(err.to_string(), "Invalid configuration at environment");
+    }
+
+    #[test]
+    fn it_fails_without_configuration() {
+        assert!(ConfLayers::load_from(None, None, None, []).is_err());
+    }
+}
@@ -1,2 +1,12 @@
 pub static TMUTIL_ATTR: &str = "com.apple.metadata:com_apple_backup_excludeItem";
+pub static OWNER_ATTR: &str = "com.reeywhaar.tmbliss";
 pub static TMBLISS_FILE: &str = ".tmbliss";
+pub static GLOBAL_TMBLISS_FILE: &str = "tmbliss";
+pub static CONFIG_FILE: &str = "config";
+pub static HOME_CONFIG_FILE: &str = ".tmbliss.json";
+pub static SYSTEM_CONFIG_DIR: &str = "/etc/tmbliss";
+pub static CONFIG_ENV: &str = "TMBLISS_CONFIG";
+pub static ENV_PREFIX: &str = "TMBLISS_";
+pub static CONF_VERSION: u32 = 1;
+pub static EXPIRING_FILE: &str = "expiring.json";
+pub static HISTORY_FILE: &str = "history.jsonl";
@@ -0,0 +1,29 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+/// User home directory
+pub fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Tmbliss configuration directory, `$XDG_CONFIG_HOME/tmbliss` or `~/.config/tmbliss`
+pub fn config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| Path::new(dir).is_absolute())
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".config")))
+        .map(|dir| dir.join("tmbliss"))
+}
+
+/// Tmbliss state directory, `$XDG_STATE_HOME/tmbliss` or `~/.local/state/tmbliss`
+pub fn state_dir() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .filter(|dir| Path::new(dir).is_absolute())
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".local/state")))
+        .map(|dir| dir.join("tmbliss"))
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+/// Units of durations with their length in seconds
+const UNITS: [(&str, u64); 5] = [
+    ("s", 1),
+    ("m", 60),
+    ("h", 60 * 60),
+    ("d", 24 * 60 * 60),
+    ("w", 7 * 24 * 60 * 60),
+];
+
+/// Parses durations like `90s`, `45m`, `12h`, `30d` and `2w`
+pub fn parse(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let Some((unit, seconds)) = UNITS.iter().find(|(unit, _)| value.ends_with(unit)) else {
+        bail!("Duration `{}` has no unit, use one of s, m, h, d, w", value);
+    };
+    let amount = value[..value.len() - unit.len()]
+        .parse::<u64>()
+        .with_context(|| format!("Invalid duration `{}`", value))?;
+    match amount.checked_mul(*seconds) {
+        Some(seconds) => Ok(Duration::from_secs(seconds)),
+        None => bail!("Duration `{}` is too long", value),
+    }
+}
+
+/// Formats duration with the largest unit and the next one, e.g. `13d 4h`.
+/// Weeks are shown as days
+pub fn format(duration: Duration) -> String {
+    let units = &UNITS[..UNITS.len() - 1];
+    let total = duration.as_secs();
+    let index = units
+        .iter()
+        .rposition(|(_, seconds)| total >= *seconds)
+        .unwrap_or(0);
+    let (unit, seconds) = units[index];
+    let mut formatted = format!("{}{}", total / seconds, unit);
+    if index > 0 {
+        let (next_unit, next_seconds) = units[index - 1];
+        let next = total % seconds / next_seconds;
+        if next > 0 {
+            formatted.push_str(&format!(" {}{}", next, next_unit));
+        }
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("90s", 90 ; "seconds")]
+    #[test_case("45m", 45 * 60 ; "minutes")]
+    #[test_case("12h", 12 * 60 * 60 ; "hours")]
+    #[test_case("30d", 30 * 24 * 60 * 60 ; "days")]
+    #[test_case("2w", 14 * 24 * 60 * 60 ; "weeks")]
+    fn it_parses_duration(value: &str, seconds: u64) {
+        assert_eq!(parse(value).unwrap(), Duration::from_secs(seconds));
+    }
+
+    #[test_case(0, "0s" ; "zero")]
+    #[test_case(90, "1m 30s" ; "minutes")]
+    #[test_case(14 * 24 * 60 * 60 + 5, "14d" ; "days")]
+    #[test_case(13 * 24 * 60 * 60 + 4 * 60 * 60 + 59, "13d 4h" ; "days and hours")]
+    fn it_formats_duration(seconds: u64, formatted: &str) {
+        assert_eq!(format(Duration::from_secs(seconds)), formatted);
+    }
+
+    #[test_case("30" ; "without unit")]
+    #[test_case("d" ; "without amount")]
+    #[test_case("-1d" ; "negative")]
+    #[test_case("1.5h" ; "fractional")]
+    #[test_case("999999999999999999w" ; "overflowing")]
+    fn it_rejects_invalid_duration(value: &str) {
+        assert!(parse(value).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+use std::{
+    env,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+
+use crate::dirs;
+
+/// Options with lists of paths, entries may be globs
+const PATH_LIST_OPTIONS: [&str; 5] = [
+    "paths",
+    "allowlist_path",
+    "skip_path",
+    "exclude_paths",
+    "protected_path",
+];
+
+/// Path lists where a glob that matches nothing is most likely a mistake
+const MATCH_REQUIRED_OPTIONS: [&str; 1] = ["paths"];
+
+/// Path lists where a glob that matches nothing is kept as is, to be reported
+/// as a missing path, or skipped with `missing_ok`
+const MATCH_KEPT_OPTIONS: [&str; 1] = ["exclude_paths"];
+
+/// Options with a single file path, `-` stands for standard input
+const FILE_OPTIONS: [&str; 3] = ["paths_from", "exclude_from", "tmbliss_file"];
+
+/// Expands `~`, `$VAR` and `${VAR}` in path options of a configuration layer and
+/// resolves relative paths against the base directory, e.g. directory of the
+/// configuration file. Entries of path lists are expanded as globs (`~/Dev/*/`),
+/// globs of `paths` must match something. Globs of `exclude_paths` that match nothing
+/// are kept, as `missing_ok` may be set in another layer.
+/// Options of profiles are expanded the same way
+pub fn expand_options(options: &mut Map<String, Value>, base: Option<&Path>) -> Result<()> {
+    for key in PATH_LIST_OPTIONS {
+        let Some(Value::Array(items)) = options.get(key) else {
+            continue;
+        };
+        let mut expanded = vec![];
+        for item in items {
+            let Value::String(item) = item else {
+                bail!("Invalid {} entry {}", key, item);
+            };
+            let matches = expand_glob(item, base)?;
+            if matches.is_empty() && MATCH_REQUIRED_OPTIONS.contains(&key) {
+                bail!("{} glob `{}` matches nothing", key, item);
+            }
+            if matches.is_empty() && MATCH_KEPT_OPTIONS.contains(&key) {
+                let path = expand_path(item, base)?.to_string_lossy().into_owned();
+                expanded.push(Value::String(path));
+                continue;
+            }
+            expanded.extend(matches.into_iter().map(Value::String));
+        }
+        options.insert(key.to_string(), Value::Array(expanded));
+    }
+
+    for key in FILE_OPTIONS {
+        if let Some(Value::String(path)) = options.get_mut(key) {
+            if path != "-" {
+                *path = expand_path(path, base)?.to_string_lossy().into_owned();
+            }
+        }
+    }
+
+    if let Some(Value::Array(rules)) = options.get_mut("rules") {
+        for rule in rules {
+            if let Some(Value::String(path)) = rule.get_mut("path") {
+                *path = expand_path(path, base)?.to_string_lossy().into_owned();
+            }
+        }
+    }
+
+    if let Some(Value::Object(profiles)) = options.get_mut("profiles") {
+        for profile in profiles.values_mut() {
+            if let Value::Object(profile) = profile {
+                expand_options(profile, base)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Expands path and resolves it against the base directory if it's relative
+pub fn expand_path(path: &str, base: Option<&Path>) -> Result<PathBuf> {
+    let expanded = expand_vars(path)?;
+    let expanded = match expanded.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let home = dirs::home_dir().context("Can't expand ~, home directory is unknown")?;
+            format!("{}{}", home.display(), rest)
+        }
+        _ => expanded,
+    };
+
+    let path = PathBuf::from(expanded);
+    match base {
+        Some(base) if path.is_relative() => {
+            let relative = path
+                .components()
+                .filter(|component| *component != Component::CurDir)
+                .collect::<PathBuf>();
+            if relative.as_os_str().is_empty() {
+                return Ok(base.to_path_buf());
+            }
+            Ok(base.join(relative))
+        }
+        _ => Ok(path),
+    }
+}
+
+/// Expands path, then glob pattern in it. Pattern with trailing `/` matches
+/// only directories. Paths without glob characters are returned as is,
+/// even if they don't exist
+pub fn expand_glob(path: &str, base: Option<&Path>) -> Result<Vec<String>> {
+    let only_dirs = path.ends_with('/');
+    let path = expand_path(path, base)?.to_string_lossy().into_owned();
+    if !path.contains(['*', '?', '[']) {
+        return Ok(vec![path]);
+    }
+    let mut matches = vec![];
+    for entry in glob::glob(&path).with_context(|| format!("Invalid glob {}", path))? {
+        let entry = entry?;
+        if only_dirs && !entry.is_dir() {
+            continue;
+        }
+        matches.push(entry.to_string_lossy().into_owned());
+    }
+    Ok(matches)
+}
+
+/// Replaces `$VAR` and `${VAR}` with environment variable values
+fn expand_vars(s: &str) -> Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(index) = rest.find('$') {
+        out.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+        let (name, tail) = match rest.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => bail!("Unclosed variable in {}", s),
+            },
+            None => {
+                let end = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            }
+        };
+        if name.is_empty() {
+            out.push('$');
+            continue;
+        }
+        let value =
+            env::var(name).with_context(|| format!("Undefined variable {} in {}", name, s))?;
+        out.push_str(&value);
+        rest = tail;
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use crate::test_utils::TestDir;
+
+    use super::*;
+
+    #[test]
+    fn it_expands_paths() {
+        let home = dirs::home_dir().unwrap();
+        let path = env::var("PATH").unwrap();
+
+        assert_eq!(expand_path("~/Dev", None).unwrap(), home.join("Dev"));
+        assert_eq!(
+            expand_path("${PATH}/x", None).unwrap(),
+            PathBuf::from(format!("{}/x", path))
+        );
+        assert_eq!(
+            expand_path("./Dev/$", Some(Path::new("/conf"))).unwrap(),
+            PathBuf::from("/conf/Dev/$")
+        );
+        assert_eq!(
+            expand_path(".", Some(Path::new("/conf"))).unwrap(),
+            PathBuf::from("/conf")
+        );
+        assert_eq!(
+            expand_path("/abs", Some(Path::new("/conf"))).unwrap(),
+            PathBuf::from("/abs")
+        );
+        assert!(expand_path("$TMBLISS_UNDEFINED_VARIABLE/x", None).is_err());
+    }
+
+    #[test]
+    fn it_expands_options() {
+        let dir = TestDir::new();
+        fs::create_dir_all(dir.join("Dev/a")).unwrap();
+        fs::create_dir_all(dir.join("Dev/b")).unwrap();
+        fs::write(dir.join("Dev/file"), "").unwrap();
+
+        let Value::Object(mut options) = json!({
+            "paths": ["Dev/*/"],
+            "skip_path": ["./missing"],
+            "exclude_from": "-",
+            "tmbliss_file": "tmbliss",
+            "rules": [{"action": "skip", "path": "vendor"}],
+            "profiles": {"work": {"paths": ["Work"]}},
+        }) else {
+            unreachable!()
+        };
+        expand_options(&mut options, Some(dir.path())).unwrap();
+
+        let dir = dir.path().display();
+        assert_eq!(
+            Value::Object(options),
+            json!({
+                "paths": [format!("{}/Dev/a", dir), format!("{}/Dev/b", dir)],
+                "skip_path": [format!("{}/missing", dir)],
+                "exclude_from": "-",
+                "tmbliss_file": format!("{}/tmbliss", dir),
+                "rules": [{"action": "skip", "path": format!("{}/vendor", dir)}],
+                "profiles": {"work": {"paths": [format!("{}/Work", dir)]}},
+            })
+        );
+    }
+
+    #[test]
+    fn it_rejects_globs_matching_nothing() {
+        let dir = TestDir::new();
+        fs::create_dir_all(dir.join("Dev")).unwrap();
+
+        let Value::Object(mut options) = json!({"paths": ["Dev"], "skip_path": ["Dev/*/"]}) else {
+            unreachable!()
+        };
+        expand_options(&mut options, Some(dir.path())).unwrap();
+        assert_eq!(options["skip_path"], json!([]));
+
+        let Value::Object(mut options) = json!({"paths": ["Dev/*/"]}) else {
+            unreachable!()
+        };
+        let err = expand_options(&mut options, Some(dir.path())).unwrap_err();
+        assert_eq!(err.to_string(), "paths glob `Dev/*/` matches nothing");
+
+        // Left for the run to report, or to skip with missing_ok
+        let Value::Object(mut options) = json!({"exclude_paths": ["Dev/*/"]}) else {
+            unreachable!()
+        };
+        expand_options(&mut options, Some(dir.path())).unwrap();
+        assert_eq!(
+            options["exclude_paths"],
+            json!([format!("{}/Dev/*", dir.path().display())])
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::logger::Logger;
+use crate::owner::{Owner, OwnerKind};
+use crate::time_machine::TimeMachine;
+
+/// Index of exclusions made with `tmbliss exclude --ttl`, kept in the state directory,
+/// so expired exclusions are found without walking the disk.
+/// Expiry itself lives in the owner marker of the path
+#[derive(Serialize, Deserialize, Default)]
+pub struct ExpiryIndex {
+    pub paths: BTreeMap<PathBuf, DateTime<Utc>>,
+}
+
+impl ExpiryIndex {
+    /// Reads the index, missing file is an empty index
+    pub fn load(file: &Path) -> Result<Self> {
+        if !file.exists() {
+            return Ok(Self::default());
+        }
+        let content =
+            fs::read_to_string(file).with_context(|| format!("Can't read {}", file.display()))?;
+        serde_json::from_str(&content).with_context(|| format!("Can't parse {}", file.display()))
+    }
+
+    pub fn save(&self, file: &Path) -> Result<()> {
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Cannot create directory {}", dir.display()))?;
+        }
+        fs::write(file, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("Cannot write {}", file.display()))
+    }
+}
+
+/// Excludes paths right away, recording expiry if ttl is set.
+/// Exclusions without ttl are permanent, even if they used to expire.
+/// If excluding a path fails, the index still records paths excluded before it
+pub fn exclude(
+    paths: &[PathBuf],
+    ttl: Option<Duration>,
+    file: &Path,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let expires = ttl
+        .map(|ttl| {
+            chrono::Duration::from_std(ttl)
+                .ok()
+                .and_then(|ttl| now.checked_add_signed(ttl))
+                .context("Ttl is too long")
+        })
+        .transpose()?;
+    let owner = Owner {
+        run: Owner::run_id(now),
+        time: now,
+        kind: OwnerKind::Manual,
+        reason: "exclude".to_string(),
+        expires,
+    };
+    let mut index = ExpiryIndex::load(file)?;
+    // All paths are checked before any is excluded, so a bad path doesn't leave
+    // earlier ones excluded without an index entry
+    let paths = paths
+        .iter()
+        .map(|path| {
+            path.canonicalize()
+                .with_context(|| format!("Can't canonicalize path {}", path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    for path in paths {
+        if let Err(e) = TimeMachine::add_exclusion(&path, Some(&owner)) {
+            index.save(file)?;
+            return Err(e).with_context(|| format!("Can't exclude path {}", path.display()));
+        }
+        match expires {
+            Some(expires) => index.paths.insert(path, expires),
+            None => index.paths.remove(&path),
+        };
+    }
+    index.save(file)
+}
+
+/// Lifts exclusions whose ttl has passed, so they come back into backup.
+/// Paths that were reset or excluded again without ttl since are just dropped
+/// from the index. Paths that can't be lifted are logged and kept in the index
+/// to retry next time. In dry run nothing is changed. Returns lifted paths
+pub fn lift_expired(
+    file: &Path,
+    now: DateTime<Utc>,
+    dry_run: bool,
+    logger: &Logger,
+) -> Result<Vec<PathBuf>> {
+    let mut index = ExpiryIndex::load(file)?;
+    let expired = index
+        .paths
+        .iter()
+        .filter(|(_, expires)| **expires <= now)
+        .map(|(path, _)| path.clone())
+        .collect::<Vec<_>>();
+    if expired.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut lifted = vec![];
+    for path in expired {
+        match lift(&path, now, dry_run) {
+            Ok(true) => lifted.push(path.clone()),
+            Ok(false) => {}
+            Err(e) => {
+                logger.log(
+                    "warning",
+                    &format!("Can't lift exclusion of {}: {:#}", path.display(), e),
+                );
+                continue;
+            }
+        }
+        index.paths.remove(&path);
+    }
+    if !dry_run {
+        index.save(file)?;
+    }
+    Ok(lifted)
+}
+
+/// Lifts exclusion of the path if it's still the expired one. Returns false
+/// if the path was reset or excluded again since
+fn lift(path: &Path, now: DateTime<Utc>, dry_run: bool) -> Result<bool> {
+    let owner = match path.exists() {
+        true => TimeMachine::owner(path)?,
+        false => None,
+    };
+    if !owner.is_some_and(|owner| owner.is_expired(now)) || !TimeMachine::is_excluded(path)? {
+        return Ok(false);
+    }
+    if !dry_run {
+        TimeMachine::remove_exclusion(path)?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::TestDir;
+
+    use super::*;
+
+    #[test]
+    fn it_lifts_expired_exclusions() {
+        let logger = Logger {
+            filter: None,
+            print0: false,
+        };
+        let dir = TestDir::new();
+        let file = dir.join("state/expiring.json");
+        let now = Utc::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        for name in ["dataset", "image", "kept"] {
+            fs::create_dir_all(dir.join(name)).unwrap();
+        }
+        let path = |name: &str| dir.join(name).canonicalize().unwrap();
+
+        exclude(&[path("dataset"), path("kept")], Some(day), &file, now).unwrap();
+        exclude(&[path("image")], Some(day * 14), &file, now).unwrap();
+        // Excluding again without ttl makes exclusion permanent
+        exclude(&[path("kept")], None, &file, now).unwrap();
+        assert_eq!(
+            ExpiryIndex::load(&file)
+                .unwrap()
+                .paths
+                .keys()
+                .collect::<Vec<_>>(),
+            [&path("dataset"), &path("image")]
+        );
+        assert_eq!(
+            TimeMachine::owner(&path("image")).unwrap().unwrap().expires,
+            Some(now + chrono::Duration::days(14))
+        );
+
+        let later = now + chrono::Duration::days(2);
+        assert_eq!(
+            lift_expired(&file, now, false, &logger).unwrap(),
+            Vec::<PathBuf>::new()
+        );
+        assert_eq!(
+            lift_expired(&file, later, true, &logger).unwrap(),
+            [path("dataset")]
+        );
+        assert!(TimeMachine::is_excluded(&path("dataset")).unwrap());
+
+        assert_eq!(
+            lift_expired(&file, later, false, &logger).unwrap(),
+            [path("dataset")]
+        );
+        assert!(!TimeMachine::is_excluded(&path("dataset")).unwrap());
+        assert!(TimeMachine::is_excluded(&path("image")).unwrap());
+        assert!(TimeMachine::is_excluded(&path("kept")).unwrap());
+        assert_eq!(
+            ExpiryIndex::load(&file)
+                .unwrap()
+                .paths
+                .keys()
+                .collect::<Vec<_>>(),
+            [&path("image")]
+        );
+    }
+
+    #[test]
+    fn it_rejects_too_long_ttl() {
+        let dir = TestDir::new();
+        let file = dir.join("state/expiring.json");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let ttl = crate::duration::parse("1000000000w").unwrap();
+
+        let result = exclude(&[dir.join("data")], Some(ttl), &file, Utc::now());
+
+        assert_eq!(result.unwrap_err().to_string(), "Ttl is too long");
+        assert!(!file.exists());
+    }
+}
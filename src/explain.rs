@@ -0,0 +1,544 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::OsStr,
+    fmt::Display,
+    io::{BufRead, Write},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use crate::conf::{Conf, Pattern, RuleAction};
+use crate::constants::TMBLISS_FILE;
+use crate::git::{Git, GitMatch};
+use crate::logger::Logger;
+use crate::matcher::{Matcher, Rule};
+use crate::protect;
+use crate::time_machine::TimeMachine;
+
+/// What a run would do with a path
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Exclude,
+    Keep,
+    Skip,
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Action::Exclude => "exclude",
+            Action::Keep => "keep",
+            Action::Skip => "skip",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Decision chain for a single path
+pub struct Explanation {
+    pub path: PathBuf,
+    /// Directory the path is processed from, none if it's outside of configured paths
+    pub root: Option<PathBuf>,
+    /// Ignore file pattern that matched the path or one of its ancestors
+    pub git: Option<GitMatch>,
+    /// Rule or .tmbliss pattern that decides what to do with the path itself
+    pub rule: Option<Rule>,
+    /// The path or its ancestor that is already excluded from backup
+    pub excluded: Option<PathBuf>,
+    pub action: Action,
+    pub reason: String,
+}
+
+impl Explanation {
+    pub fn log(&self, logger: &Logger) {
+        logger.log("path", &self.path.to_string_lossy());
+        match &self.root {
+            Some(root) => logger.log("root", &root.to_string_lossy()),
+            None => logger.log("root", "none"),
+        }
+        match &self.git {
+            Some(git) => logger.log("gitignore", &Self::describe_git(git)),
+            None => logger.log("gitignore", "no match"),
+        }
+        match &self.rule {
+            Some(rule) => logger.log("rule", &Self::describe_rule(rule)),
+            None => logger.log("rule", "no match"),
+        }
+        match &self.excluded {
+            Some(excluded) => logger.log("excluded", &excluded.to_string_lossy()),
+            None => logger.log("excluded", "no"),
+        }
+        logger.log("action", &format!("{} ({})", self.action, self.reason));
+    }
+
+    fn describe_git(git: &GitMatch) -> String {
+        let source = match (&git.file, git.line) {
+            (Some(file), Some(line)) => format!("{}:{}", file.display(), line),
+            (Some(file), None) => file.display().to_string(),
+            (None, _) => "<unknown>".to_string(),
+        };
+        format!("{}: {} ({})", source, git.pattern, git.path.display())
+    }
+
+    /// Describes rule, adding line number for patterns of .tmbliss files
+    pub fn describe_rule(rule: &Rule) -> String {
+        let file = Path::new(&rule.source);
+        match &rule.pattern {
+            Pattern::Glob(glob) if file.is_file() => match Git::find_line(file, glob) {
+                Some(line) => format!("{}:{}: {} glob {}", rule.source, line, rule.action, glob),
+                None => rule.to_string(),
+            },
+            _ => rule.to_string(),
+        }
+    }
+}
+
+/// Answers why a path is or isn't excluded, following the same decision
+/// chain as a run but without walking the whole tree.
+///
+/// If configuration has no paths, the path is checked from its git repository
+/// root, or from its parent directory outside of a repository
+pub struct Explainer {
+    conf: Conf,
+    roots: RefCell<HashMap<PathBuf, Root>>,
+}
+
+/// Matchers and git ignore rules of a root, kept between queries
+/// so paths sharing ancestors don't read the same files again
+struct Root {
+    matcher: Matcher,
+    git: Git,
+    /// Matchers with .tmbliss files of the root and all directories down to the key
+    matchers: HashMap<PathBuf, Matcher>,
+    gitignores: HashMap<PathBuf, (GitignoreBuilder, Gitignore)>,
+}
+
+impl Root {
+    fn new(conf: &Conf, root: &Path) -> Result<Self> {
+        Ok(Self {
+            matcher: Matcher::new(conf, root)?,
+            git: Git {
+                path: root.to_path_buf(),
+                ignore_case: Git::ignore_case(root),
+            },
+            matchers: HashMap::new(),
+            gitignores: HashMap::new(),
+        })
+    }
+
+    /// Returns matcher for the directory, reading .tmbliss files from the root
+    /// down to it that weren't read yet
+    fn matcher(&mut self, dir: &Path) -> Result<&Matcher> {
+        if !self.matchers.contains_key(dir) {
+            let parent = match dir.parent().filter(|_| dir != self.git.path) {
+                Some(parent) => self.matcher(parent)?,
+                None => &self.matcher,
+            };
+            let matcher = parent
+                .with_tmbliss_file(dir)
+                .with_context(|| format!("Can't read .tmbliss in {}", dir.display()))?;
+            self.matchers.insert(dir.to_path_buf(), matcher);
+        }
+        Ok(&self.matchers[dir])
+    }
+}
+
+impl Explainer {
+    pub fn new(mut conf: Conf) -> Result<Self> {
+        conf.read_lists()?;
+        let mut roots = HashMap::new();
+        for path in &conf.paths {
+            let root = Path::new(path)
+                .canonicalize()
+                .with_context(|| format!("Can't canonicalize path {}", path))?;
+            let cache = Root::new(&conf, &root)?;
+            roots.insert(root, cache);
+        }
+        Ok(Self {
+            conf,
+            roots: RefCell::new(roots),
+        })
+    }
+
+    pub fn explain(&self, path: &Path) -> Result<Explanation> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Can't canonicalize path {}", path.display()))?;
+
+        let excluded = if TimeMachine::is_excluded_deep(&path)
+            .with_context(|| format!("Can't check exclusion of {}", path.display()))?
+        {
+            path.ancestors()
+                .find(|ancestor| TimeMachine::is_excluded(ancestor).unwrap_or(false))
+                .map(Path::to_path_buf)
+        } else {
+            None
+        };
+
+        let mut explanation = Explanation {
+            root: self.root(&path)?,
+            path,
+            git: None,
+            rule: None,
+            excluded,
+            action: Action::Keep,
+            reason: "not ignored by git".to_string(),
+        };
+
+        if let Some(exclude_path) = self.exclude_path(&explanation.path) {
+            explanation.action = Action::Exclude;
+            explanation.reason = format!("exclude_paths: {}", exclude_path.display());
+            return Ok(explanation);
+        }
+
+        let Some(root) = explanation.root.clone() else {
+            explanation.reason = "outside of configured paths".to_string();
+            return Ok(explanation);
+        };
+        let mut roots = self.roots.borrow_mut();
+        let cache = roots
+            .get_mut(&root)
+            .expect("Root is registered by Explainer::root");
+
+        let mut chain = explanation
+            .path
+            .ancestors()
+            .take_while(|ancestor| ancestor.starts_with(&root))
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        chain.reverse();
+
+        // Each item of the chain is checked with .tmbliss files of the root
+        // and of all directories down to the item's parent
+        let mut matchers = vec![];
+        for item in &chain {
+            let dir = item.parent().filter(|_| *item != root).unwrap_or(&root);
+            matchers.push(cache.matcher(dir)?.clone());
+        }
+
+        explanation.rule = matchers
+            .last()
+            .and_then(|matcher| matcher.decide(&explanation.path));
+        explanation.git = cache
+            .git
+            .match_path_cached(&explanation.path, &mut cache.gitignores)?;
+
+        // Ignored directory that holds protected items is excluded around them,
+        // so its children are decided as ignored items too
+        let mut descended = false;
+        for (item, matcher) in chain.iter().zip(&matchers) {
+            let ignored = descended
+                || explanation
+                    .git
+                    .as_ref()
+                    .is_some_and(|git| git.path == *item);
+            descended = false;
+            let Some((action, reason)) = Self::decide(item, matcher, &explanation, ignored) else {
+                continue;
+            };
+            descended = ignored && action == Action::Keep && matcher.decide(item).is_none();
+            explanation.action = action;
+            explanation.reason = if *item != explanation.path {
+                format!("{} of ancestor {}", reason, item.display())
+            } else {
+                reason
+            };
+            // Allowlisted item is kept, but its children are still processed
+            if action != Action::Keep {
+                break;
+            }
+        }
+
+        Ok(explanation)
+    }
+
+    /// Answers queries for paths read from the input, like `git check-ignore -v --stdin`.
+    /// Writes a record per path: action, whether the path is currently excluded,
+    /// deciding rule and the path itself, separated by tabs. Path that can't be checked
+    /// gets `error`, the error and the path instead, and the batch goes on. With `nul`
+    /// input paths are NUL separated and every output field is NUL terminated
+    pub fn check(&self, input: impl BufRead, mut output: impl Write, nul: bool) -> Result<()> {
+        let delimiter = if nul { b'\0' } else { b'\n' };
+        for record in input.split(delimiter) {
+            let record = record.context("Can't read paths")?;
+            if record.is_empty() {
+                continue;
+            }
+            let path = Path::new(OsStr::from_bytes(&record));
+            let fields = match self.explain(path) {
+                Ok(explanation) => {
+                    let state = match explanation.excluded {
+                        Some(_) => "excluded",
+                        None => "included",
+                    };
+                    vec![
+                        explanation.action.to_string(),
+                        state.to_string(),
+                        explanation.reason,
+                    ]
+                }
+                Err(e) => vec!["error".to_string(), format!("{:#}", e)],
+            };
+            let fields = fields
+                .iter()
+                .map(|field| field.as_bytes())
+                .chain([record.as_slice()])
+                .collect::<Vec<_>>();
+            if nul {
+                for field in fields {
+                    output.write_all(field)?;
+                    output.write_all(b"\0")?;
+                }
+            } else {
+                output.write_all(&fields.join(&b'\t'))?;
+                output.write_all(b"\n")?;
+            }
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Decides what a run does with an item of the chain, in the order
+    /// `process_directory` checks it
+    fn decide(
+        item: &Path,
+        matcher: &Matcher,
+        explanation: &Explanation,
+        ignored: bool,
+    ) -> Option<(Action, String)> {
+        let name = item.file_name();
+        let is_ignore_file =
+            name == Some(OsStr::new(TMBLISS_FILE)) || name == Some(OsStr::new(".gitignore"));
+        if item.is_file() && is_ignore_file {
+            return Some((Action::Skip, "ignore file".to_string()));
+        }
+        if Git::is_git(item) {
+            return Some((Action::Skip, "git directory".to_string()));
+        }
+        let rule = matcher.decide(item);
+        if let Some(rule) = rule.as_ref().filter(|rule| rule.action == RuleAction::Skip) {
+            return Some((
+                Action::Skip,
+                format!("skipped by {}", Explanation::describe_rule(rule)),
+            ));
+        }
+        if item.is_dir() && explanation.excluded.as_deref() == Some(item) {
+            return Some((Action::Exclude, "already excluded".to_string()));
+        }
+        match rule {
+            Some(rule) if rule.action == RuleAction::Exclude => Some((
+                Action::Exclude,
+                format!("excluded by {}", Explanation::describe_rule(&rule)),
+            )),
+            Some(rule) if ignored => Some((
+                Action::Keep,
+                format!(
+                    "ignored by git, allowlisted by {}",
+                    Explanation::describe_rule(&rule)
+                ),
+            )),
+            None if ignored
+                && item.is_dir()
+                && protect::holds_protected(item, |entry| matcher.is_allowlisted(entry)) =>
+            {
+                Some((
+                    Action::Keep,
+                    "ignored by git, holds protected items".to_string(),
+                ))
+            }
+            None if ignored => Some((Action::Exclude, "ignored by git".to_string())),
+            _ => None,
+        }
+    }
+
+    fn root(&self, path: &Path) -> Result<Option<PathBuf>> {
+        if !self.conf.paths.is_empty() {
+            return Ok(self
+                .roots
+                .borrow()
+                .keys()
+                .filter(|root| path.starts_with(root))
+                .max_by_key(|root| root.components().count())
+                .cloned());
+        }
+
+        let root = path
+            .ancestors()
+            .find(|ancestor| ancestor.join(".git").exists())
+            .or_else(|| path.parent())
+            .unwrap_or(path)
+            .to_path_buf();
+        if !self.roots.borrow().contains_key(&root) {
+            let cache = Root::new(&self.conf, &root)?;
+            self.roots.borrow_mut().insert(root.clone(), cache);
+        }
+        Ok(Some(root))
+    }
+
+    fn exclude_path(&self, path: &Path) -> Option<PathBuf> {
+        self.conf
+            .exclude_paths
+            .iter()
+            .filter_map(|item| Path::new(item).canonicalize().ok())
+            .find(|item| path.starts_with(item))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filetree::FileTree;
+
+    use super::*;
+
+    #[test]
+    fn it_explains_decision_chain() {
+        let filetree = FileTree::new_test_repo();
+        let fmap = filetree.create();
+        let dir = fmap.get("__workspace").unwrap().canonicalize().unwrap();
+        let explainer = Explainer::new(Conf {
+            paths: vec![dir.to_string_lossy().to_string()],
+            allowlist_glob: vec!["not_excluded_path".to_string()],
+            skip_glob: vec!["directory_with_subgitignore".to_string()],
+            ..Conf::default()
+        })
+        .unwrap();
+
+        let explanation = explainer
+            .explain(&dir.join("excluded_path/file.txt"))
+            .unwrap();
+        assert_eq!(explanation.root, Some(dir.clone()));
+        assert_eq!(explanation.git.unwrap().line, Some(1));
+        assert_eq!(explanation.action, Action::Exclude);
+        assert!(explanation.reason.starts_with("ignored by git of ancestor"));
+
+        let explanation = explainer.explain(&dir.join("not_excluded_path")).unwrap();
+        assert_eq!(explanation.action, Action::Keep);
+        assert_eq!(
+            explanation.reason,
+            "ignored by git, allowlisted by allowlist_glob: allow glob not_excluded_path"
+        );
+
+        let explanation = explainer
+            .explain(&dir.join("directory_with_subgitignore/subignore.txt"))
+            .unwrap();
+        assert_eq!(explanation.action, Action::Skip);
+
+        let explanation = explainer
+            .explain(&dir.join("nested_dir/included_file.txt"))
+            .unwrap();
+        assert!(explanation.git.is_none());
+        assert_eq!(explanation.action, Action::Keep);
+
+        TimeMachine::add_exclusion(&dir.join("nested_dir"), None).unwrap();
+        let explanation = explainer
+            .explain(&dir.join("nested_dir/included_file.txt"))
+            .unwrap();
+        assert_eq!(explanation.excluded, Some(dir.join("nested_dir")));
+        assert_eq!(explanation.action, Action::Exclude);
+        assert!(explanation
+            .reason
+            .starts_with("already excluded of ancestor"));
+
+        // Directories are read once and kept for the following paths
+        let roots = explainer.roots.borrow();
+        let cache = &roots[&dir];
+        assert!(cache.matchers.contains_key(&dir.join("nested_dir")));
+        assert!(cache.gitignores.contains_key(&dir.join("nested_dir")));
+        assert!(!cache
+            .gitignores
+            .contains_key(&dir.join("nested_dir/included_file.txt")));
+    }
+
+    #[test]
+    fn it_explains_protected_items_inside_ignored_directory() {
+        let filetree = FileTree::new_test_repo();
+        let fmap = filetree.create();
+        let dir = fmap.get("__workspace").unwrap().canonicalize().unwrap();
+        std::fs::write(dir.join("excluded_path/.env"), "").unwrap();
+        let explainer = Explainer::new(Conf {
+            paths: vec![dir.to_string_lossy().to_string()],
+            ..Conf::default()
+        })
+        .unwrap();
+
+        let explanation = explainer.explain(&dir.join("excluded_path")).unwrap();
+        assert_eq!(explanation.action, Action::Keep);
+        assert_eq!(explanation.reason, "ignored by git, holds protected items");
+
+        let explanation = explainer.explain(&dir.join("excluded_path/.env")).unwrap();
+        assert_eq!(explanation.action, Action::Keep);
+        assert_eq!(
+            explanation.reason,
+            "ignored by git, allowlisted by builtin_allowlist: allow glob .env"
+        );
+
+        let explanation = explainer
+            .explain(&dir.join("excluded_path/file.txt"))
+            .unwrap();
+        assert_eq!(explanation.action, Action::Exclude);
+        assert_eq!(explanation.reason, "ignored by git");
+    }
+
+    #[test]
+    fn it_checks_paths_from_input() {
+        let filetree = FileTree::new_test_repo();
+        let fmap = filetree.create();
+        let dir = fmap.get("__workspace").unwrap().canonicalize().unwrap();
+        let explainer = Explainer::new(Conf {
+            paths: vec![dir.to_string_lossy().to_string()],
+            ..Conf::default()
+        })
+        .unwrap();
+        let excluded = dir.join("excluded_path").to_string_lossy().to_string();
+        let included = dir
+            .join("nested_dir/included_file.txt")
+            .to_string_lossy()
+            .to_string();
+
+        let mut output = vec![];
+        explainer
+            .check(
+                format!("{}\n\n{}\n", excluded, included).as_bytes(),
+                &mut output,
+                false,
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "exclude\tincluded\tignored by git\t{}\nkeep\tincluded\tnot ignored by git\t{}\n",
+                excluded, included
+            )
+        );
+
+        let missing = dir.join("missing").to_string_lossy().to_string();
+        let mut output = vec![];
+        explainer
+            .check(
+                format!("{}\n{}\n", missing, included).as_bytes(),
+                &mut output,
+                false,
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "error\tCan't canonicalize path {}: No such file or directory (os error 2)\t{}\nkeep\tincluded\tnot ignored by git\t{}\n",
+                missing, missing, included
+            )
+        );
+
+        let mut output = vec![];
+        explainer
+            .check(format!("{}\0", excluded).as_bytes(), &mut output, true)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("exclude\0included\0ignored by git\0{}\0", excluded)
+        );
+    }
+}
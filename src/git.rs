@@ -1,13 +1,31 @@
 use std::{
+    collections::{BTreeSet, HashMap},
     fs,
     path::{Path, PathBuf},
+    process::Command,
 };
 
 use anyhow::Result;
-use ignore::gitignore::{gitconfig_excludes_path, GitignoreBuilder};
+use ignore::{
+    gitignore::{gitconfig_excludes_path, Gitignore, GitignoreBuilder},
+    Match,
+};
+
+use crate::matcher::Matcher;
 
 pub struct Git {
     pub path: PathBuf,
+    /// Match patterns case insensitively, resolved once per root with [`Git::ignore_case`]
+    pub ignore_case: bool,
+}
+
+/// Ignore file pattern that makes git ignore a path
+pub struct GitMatch {
+    /// Ignored path, either the checked path or one of its ancestors
+    pub path: PathBuf,
+    pub file: Option<PathBuf>,
+    pub line: Option<usize>,
+    pub pattern: String,
 }
 
 impl Git {
@@ -17,15 +35,7 @@ impl Git {
             return Err(anyhow::anyhow!("Path is not a directory"));
         }
 
-        let mut gitignore_builder = GitignoreBuilder::new(&self.path);
-        if let Some(gitconfig_path) = gitconfig_excludes_path() {
-            if gitconfig_path.exists() {
-                let err = gitignore_builder.add(gitconfig_path);
-                if let Some(err) = err {
-                    return Err(err.into());
-                }
-            }
-        }
+        let gitignore_builder = self.builder()?;
 
         let mut ignored: Vec<PathBuf> = vec![];
 
@@ -42,13 +52,10 @@ impl Git {
             let mut gitignore_builder = gitignore_builder.clone();
             let gitignore_file = path.join(".gitignore");
             if gitignore_file.exists() {
-                let err = gitignore_builder.add(path.join(".gitignore"));
-                if let Some(err) = err {
-                    return Err(err.into());
-                }
+                Git::add_ignore_file(&mut gitignore_builder, &gitignore_file)?;
             }
             let gitignore = gitignore_builder.build()?;
-            if gitignore.matched(path, is_dir).is_ignore() {
+            if gitignore.matched(Git::normalize(path), is_dir).is_ignore() {
                 ignored.push(path.canonicalize()?);
                 return Ok(());
             }
@@ -67,10 +74,149 @@ impl Git {
         Ok(ignored)
     }
 
+    /// Finds pattern that makes git ignore the path or one of its ancestors
+    /// (up to the git root path), checking them the same way as [`Git::get_ignores_list`]
+    pub fn match_path(&self, path: &Path) -> Result<Option<GitMatch>> {
+        self.match_path_cached(path, &mut HashMap::new())
+    }
+
+    /// Same as [`Git::match_path`], but keeps ignore rules of checked directories
+    /// in `cache`, so ignore files are read once for paths sharing ancestors
+    pub fn match_path_cached(
+        &self,
+        path: &Path,
+        cache: &mut HashMap<PathBuf, (GitignoreBuilder, Gitignore)>,
+    ) -> Result<Option<GitMatch>> {
+        let mut chain = path
+            .ancestors()
+            .take_while(|ancestor| ancestor.starts_with(&self.path))
+            .collect::<Vec<_>>();
+        chain.reverse();
+
+        for (index, item) in chain.iter().enumerate() {
+            if Self::is_git(item) {
+                return Ok(None);
+            }
+            let mut uncached = None;
+            if !cache.contains_key(*item) {
+                // Ancestors are directories, so rules of the parent are cached already
+                let mut gitignore_builder = match index.checked_sub(1) {
+                    Some(parent) => cache[chain[parent]].0.clone(),
+                    None => self.builder()?,
+                };
+                let gitignore_file = item.join(".gitignore");
+                if gitignore_file.exists() {
+                    Self::add_ignore_file(&mut gitignore_builder, &gitignore_file)?;
+                }
+                let gitignore = gitignore_builder.build()?;
+                if item.is_dir() {
+                    cache.insert(item.to_path_buf(), (gitignore_builder, gitignore));
+                } else {
+                    uncached = Some(gitignore);
+                }
+            }
+            let gitignore = match &uncached {
+                Some(gitignore) => gitignore,
+                None => &cache[*item].1,
+            };
+            if let Match::Ignore(glob) = gitignore.matched(Self::normalize(item), item.is_dir()) {
+                return Ok(Some(GitMatch {
+                    path: item.to_path_buf(),
+                    file: glob.from().map(Path::to_path_buf),
+                    line: glob
+                        .from()
+                        .and_then(|file| Self::find_line(file, glob.original())),
+                    pattern: glob.original().to_string(),
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns number of the last line of ignore file that holds the pattern
+    pub fn find_line(file: &Path, pattern: &str) -> Option<usize> {
+        let content = fs::read_to_string(file).ok()?;
+        content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| Matcher::normalize(line.trim_end(), false) == pattern)
+            .last()
+            .map(|(index, _)| index + 1)
+    }
+
+    /// Finds root of the repository that holds the path, including the path itself
+    pub fn root(path: &Path) -> Option<PathBuf> {
+        path.ancestors()
+            .find(|ancestor| ancestor.join(".git").exists())
+            .map(Path::to_path_buf)
+    }
+
+    /// Lists files tracked by git in the directory, with absolute paths.
+    /// Empty if the directory isn't inside a repository
+    pub fn tracked_files(path: &Path) -> BTreeSet<PathBuf> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(path)
+            .args(["ls-files", "-z"])
+            .output();
+        match output {
+            Ok(output) if output.status.success() => output
+                .stdout
+                .split(|byte| *byte == 0)
+                .filter(|file| !file.is_empty())
+                .map(|file| path.join(String::from_utf8_lossy(file).as_ref()))
+                .collect(),
+            _ => BTreeSet::new(),
+        }
+    }
+
     /// Checks if a directory is a git service directory (".git")
     pub fn is_git(path: &Path) -> bool {
         path.ends_with(".git")
     }
+
+    /// Reads `core.ignoreCase` of the repository that holds the path with `git config`,
+    /// so global configuration, worktrees and submodules are handled by git itself.
+    /// When the option isn't set, case sensitivity of the volume is probed instead
+    pub fn ignore_case(path: &Path) -> bool {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(path)
+            .args(["config", "--bool", "core.ignoreCase"])
+            .output();
+        match output {
+            Ok(output) if output.status.success() => {
+                String::from_utf8_lossy(&output.stdout).trim() == "true"
+            }
+            _ => Matcher::is_volume_case_insensitive(path),
+        }
+    }
+
+    fn builder(&self) -> Result<GitignoreBuilder> {
+        let mut gitignore_builder = GitignoreBuilder::new(Self::normalize(&self.path));
+        gitignore_builder.case_insensitive(self.ignore_case)?;
+        if let Some(gitconfig_path) = gitconfig_excludes_path() {
+            if gitconfig_path.exists() {
+                Self::add_ignore_file(&mut gitignore_builder, &gitconfig_path)?;
+            }
+        }
+        Ok(gitignore_builder)
+    }
+
+    /// Adds ignore file lines in NFC form, so they match paths regardless
+    /// of the form file names are stored on disk
+    fn add_ignore_file(builder: &mut GitignoreBuilder, path: &Path) -> Result<()> {
+        let content = fs::read_to_string(path)?;
+        for line in content.lines() {
+            builder.add_line(Some(path.to_path_buf()), &Matcher::normalize(line, false))?;
+        }
+        Ok(())
+    }
+
+    fn normalize(path: &Path) -> PathBuf {
+        PathBuf::from(Matcher::normalize(&path.to_string_lossy(), false).as_ref())
+    }
 }
 
 #[cfg(test)]
@@ -90,7 +236,10 @@ mod tests {
         let dir = fmap.get("__workspace").unwrap();
         assert_eq!(dir.read_dir().unwrap().count(), 9);
 
-        let git = Git { path: dir.clone() };
+        let git = Git {
+            path: dir.clone(),
+            ignore_case: false,
+        };
 
         let mut list = git.get_ignores_list().unwrap();
         list.sort();
@@ -110,6 +259,90 @@ mod tests {
         assert_eq!(list, result);
     }
 
+    #[test]
+    fn it_matches_path_with_line() {
+        let filetree = FileTree::new_test_repo();
+        let fmap = filetree.create();
+        let dir = fmap.get("__workspace").unwrap().canonicalize().unwrap();
+        let git = Git {
+            path: dir.clone(),
+            ignore_case: false,
+        };
+
+        let found = git
+            .match_path(&dir.join("excluded_path/file.txt"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.path, dir.join("excluded_path"));
+        assert_eq!(found.file, Some(dir.join(".gitignore")));
+        assert_eq!(found.line, Some(1));
+        assert_eq!(found.pattern, "/excluded_path");
+
+        let found = git
+            .match_path(&dir.join("directory_with_subgitignore/subignore.txt"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            found.file,
+            Some(dir.join("directory_with_subgitignore/.gitignore"))
+        );
+        assert_eq!(found.line, Some(1));
+
+        assert!(git
+            .match_path(&dir.join("nested_dir/included_file.txt"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn it_reads_ignore_case() {
+        let filetree = FileTree::new_test_repo();
+        let fmap = filetree.create();
+        let dir = fmap.get("__workspace").unwrap();
+        let path = dir.join("nested_dir");
+
+        let config = |args: &[&str]| {
+            Command::new("git")
+                .arg("config")
+                .args(args)
+                .current_dir(dir)
+                .output()
+                .unwrap()
+        };
+
+        config(&["core.ignoreCase", "true"]);
+        assert!(Git::ignore_case(&path));
+
+        config(&["core.ignoreCase", "false"]);
+        assert!(!Git::ignore_case(&path));
+
+        config(&["--unset", "core.ignoreCase"]);
+        assert_eq!(
+            Git::ignore_case(&path),
+            Matcher::is_volume_case_insensitive(&path)
+        );
+    }
+
+    #[test]
+    fn it_finds_tracked_files() {
+        let filetree = FileTree::new_test_repo();
+        let fmap = filetree.create();
+        let dir = fmap.get("__workspace").unwrap().canonicalize().unwrap();
+        Command::new("git")
+            .args(["add", "nested_dir/included_file.txt"])
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+
+        assert_eq!(
+            Git::tracked_files(&dir),
+            BTreeSet::from([dir.join("nested_dir/included_file.txt")])
+        );
+        assert!(Git::tracked_files(dir.parent().unwrap()).is_empty());
+        assert_eq!(Git::root(&dir.join("nested_dir")), Some(dir.clone()));
+        assert_eq!(Git::root(dir.parent().unwrap()), None);
+    }
+
     #[test]
     fn it_check_if_directory_is_git() {
         assert!(Git::is_git(&current_dir().unwrap().join(".git")));
@@ -0,0 +1,171 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use ignore::WalkBuilder;
+
+use crate::conf::Conf;
+use crate::owner::OwnerKind;
+
+/// Items visited during a run and exclusions it's going to add.
+/// Nothing is written until the plan passes guards
+#[derive(Default)]
+pub struct Plan {
+    pub processed: HashSet<PathBuf>,
+    pub new: Vec<NewExclusion>,
+    /// Items that are already excluded
+    pub excluded: Vec<PathBuf>,
+    /// Items that couldn't be checked
+    pub errors: Vec<PathBuf>,
+}
+
+/// Exclusion that the run is going to add
+pub struct NewExclusion {
+    pub path: PathBuf,
+    pub kind: OwnerKind,
+    /// Why the item is excluded: gitignore rule, `exclude_paths` or exclude rule
+    pub reason: String,
+}
+
+/// Checks new exclusions of the plan against safety guards: `max_new_exclusions`,
+/// `max_excluded_percent` of bytes of each root and `protected_path`.
+/// Repository roots are always protected. Returns reasons why the plan is suspicious
+pub fn check(plan: &Plan, conf: &Conf) -> Vec<String> {
+    let mut reasons = vec![];
+
+    if let Some(max) = conf.max_new_exclusions {
+        if plan.new.len() > max {
+            reasons.push(format!(
+                "{} new exclusions exceed max_new_exclusions {}",
+                plan.new.len(),
+                max
+            ));
+        }
+    }
+
+    let protected = conf
+        .protected_path
+        .iter()
+        .map(|path| {
+            Path::new(path)
+                .canonicalize()
+                .unwrap_or_else(|_| PathBuf::from(path))
+        })
+        .collect::<Vec<_>>();
+    for item in plan.new.iter().map(|item| &item.path) {
+        if item.join(".git").exists() {
+            reasons.push(format!("{} is a repository root", item.display()));
+        }
+        if let Some(path) = protected
+            .iter()
+            .find(|path| item.starts_with(path) || path.starts_with(item))
+        {
+            reasons.push(format!(
+                "{} is protected by protected_path {}",
+                item.display(),
+                path.display()
+            ));
+        }
+    }
+
+    if let Some(max) = conf.max_excluded_percent {
+        for root in &conf.paths {
+            let Ok(root) = Path::new(root).canonicalize() else {
+                continue;
+            };
+            let items = plan
+                .new
+                .iter()
+                .map(|item| &item.path)
+                .filter(|item| item.starts_with(&root))
+                .collect::<Vec<_>>();
+            if items.is_empty() {
+                continue;
+            }
+            let total = size(&root);
+            if total == 0 {
+                continue;
+            }
+            let excluded = items.iter().map(|item| size(item)).sum::<u64>();
+            let percent = excluded as f64 * 100.0 / total as f64;
+            if percent > max {
+                reasons.push(format!(
+                    "new exclusions take {:.1}% of {}, more than max_excluded_percent {}",
+                    percent,
+                    root.display(),
+                    max
+                ));
+            }
+        }
+    }
+
+    reasons
+}
+
+/// Total size of files in the path, or size of the file itself
+pub fn size(path: &Path) -> u64 {
+    WalkBuilder::new(path)
+        .standard_filters(false)
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|kind| kind.is_file()))
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_utils::TestDir;
+
+    use super::*;
+
+    #[test]
+    fn it_checks_plan() {
+        let dir = TestDir::new();
+        fs::create_dir_all(dir.join("root/repo/.git")).unwrap();
+        fs::create_dir_all(dir.join("root/docs")).unwrap();
+        fs::create_dir_all(dir.join("root/cache")).unwrap();
+        fs::write(dir.join("root/cache/blob"), vec![0; 900]).unwrap();
+        fs::write(dir.join("root/docs/notes.txt"), vec![0; 100]).unwrap();
+        let root = dir.join("root").canonicalize().unwrap();
+        let plan = Plan {
+            new: ["repo", "cache", "docs/notes.txt"]
+                .map(|item| NewExclusion {
+                    path: root.join(item),
+                    kind: OwnerKind::ExcludePaths,
+                    reason: "exclude_paths".to_string(),
+                })
+                .into(),
+            ..Plan::default()
+        };
+        let conf = Conf {
+            paths: vec![root.to_string_lossy().into_owned()],
+            protected_path: vec![root.join("docs").to_string_lossy().into_owned()],
+            max_new_exclusions: Some(2),
+            max_excluded_percent: Some(50.0),
+            ..Conf::default()
+        };
+
+        assert_eq!(
+            check(&plan, &conf),
+            [
+                "3 new exclusions exceed max_new_exclusions 2".to_string(),
+                format!("{}/repo is a repository root", root.display()),
+                format!(
+                    "{}/docs/notes.txt is protected by protected_path {}/docs",
+                    root.display(),
+                    root.display()
+                ),
+                format!(
+                    "new exclusions take 100.0% of {}, more than max_excluded_percent 50",
+                    root.display()
+                ),
+            ]
+        );
+        assert!(check(&plan, &Conf::default()).len() == 1);
+    }
+}
@@ -0,0 +1,349 @@
+use std::{
+    collections::BTreeSet,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::conf::Conf;
+use crate::owner::Owner;
+
+/// History file is rotated when it grows over this size. The previous file
+/// is kept with `.1` suffix, so history takes at most twice this size
+const MAX_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Exclusions of a run are listed for `history diff` only up to this many,
+/// so history of large trees isn't rotated away after a few runs
+const MAX_LISTED: usize = 2000;
+
+/// How the run ended
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    #[default]
+    Completed,
+    /// Safety guards stopped the run before excluding anything
+    Aborted,
+    /// The run failed with an error
+    Error,
+}
+
+/// Run of `tmbliss run`, `service` or `conf`, one line of history file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RunRecord {
+    /// Id of the run, the same as in owner markers of its exclusions
+    pub run: String,
+    pub started: DateTime<Utc>,
+    pub ended: DateTime<Utc>,
+    /// Hash of the configuration, tells if configuration changed between runs
+    pub conf_hash: String,
+    pub roots: Vec<String>,
+    pub dry_run: bool,
+    #[serde(default)]
+    pub status: RunStatus,
+    /// Why the run was aborted or failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Count of items excluded by the run, always zero for dry run
+    pub new: usize,
+    /// Count of items that dry run would exclude
+    #[serde(default)]
+    pub planned: usize,
+    /// Count of items that were already excluded
+    pub excluded: usize,
+    /// Count of items that couldn't be checked or excluded
+    pub errors: usize,
+    /// Items that are excluded after the run, compared by `history diff`.
+    /// Not listed if there are more than `MAX_LISTED` of them or the run failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclusions: Option<BTreeSet<PathBuf>>,
+    /// Total size of new exclusions, recorded with `history_bytes` option
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+}
+
+impl RunRecord {
+    /// Record of the run starting now, its counts are filled by the run
+    pub fn start(conf: &Conf) -> Result<Self> {
+        let started = Utc::now();
+        Ok(Self {
+            run: Owner::run_id(started),
+            started,
+            ended: started,
+            conf_hash: conf_hash(conf)?,
+            roots: conf.paths.clone(),
+            dry_run: conf.dry_run,
+            status: RunStatus::Completed,
+            error: None,
+            new: 0,
+            planned: 0,
+            excluded: 0,
+            errors: 0,
+            exclusions: None,
+            bytes: None,
+        })
+    }
+
+    /// Sets counts of new and already excluded items and lists them,
+    /// unless there are too many
+    pub fn count(&mut self, new: &[PathBuf], excluded: &[PathBuf]) {
+        self.new = new.len();
+        self.excluded = excluded.len();
+        self.exclusions = (new.len() + excluded.len() <= MAX_LISTED)
+            .then(|| new.iter().chain(excluded).cloned().collect());
+    }
+
+    pub fn describe(&self) -> String {
+        let mut description = format!(
+            "{} {} {}: new {}, excluded {}, errors {}",
+            self.run,
+            self.started.with_timezone(&chrono::Local).to_rfc3339(),
+            self.roots.join(", "),
+            self.new,
+            self.excluded,
+            self.errors
+        );
+        if let Some(bytes) = self.bytes {
+            description.push_str(&format!(", {} bytes", bytes));
+        }
+        if self.dry_run {
+            description.push_str(&format!(", dry run, would exclude {}", self.planned));
+        }
+        match self.status {
+            RunStatus::Completed => {}
+            RunStatus::Aborted => description.push_str(", aborted"),
+            RunStatus::Error => description.push_str(", error"),
+        }
+        if let Some(error) = &self.error {
+            description.push_str(&format!(": {}", error));
+        }
+        description
+    }
+
+    /// Items that are excluded after the run
+    fn exclusions(&self) -> Result<&BTreeSet<PathBuf>> {
+        self.exclusions
+            .as_ref()
+            .with_context(|| format!("Run {} has no list of exclusions to compare", self.run))
+    }
+}
+
+/// Hash of the configuration as it is serialized. FNV-1a is used, as unlike
+/// the standard hasher it doesn't change between Rust releases
+pub fn conf_hash(conf: &Conf) -> Result<String> {
+    Ok(format!(
+        "{:016x}",
+        fnv1a(serde_json::to_string(conf)?.as_bytes())
+    ))
+}
+
+/// 64-bit FNV-1a hash
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Previous history file, kept after rotation
+fn rotated(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".1");
+    PathBuf::from(name)
+}
+
+/// Appends the run to the history file, rotating it when it's too large
+pub fn record(file: &Path, run: &RunRecord) -> Result<()> {
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Cannot create directory {}", dir.display()))?;
+    }
+    if fs::metadata(file).is_ok_and(|metadata| metadata.len() > MAX_SIZE) {
+        fs::rename(file, rotated(file))
+            .with_context(|| format!("Cannot rotate {}", file.display()))?;
+    }
+    let mut history = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)
+        .with_context(|| format!("Cannot open {}", file.display()))?;
+    writeln!(history, "{}", serde_json::to_string(run)?)
+        .with_context(|| format!("Cannot write {}", file.display()))
+}
+
+/// Reads runs from the rotated and the current history files, oldest first.
+/// Missing files are an empty history
+pub fn load(file: &Path) -> Result<Vec<RunRecord>> {
+    let mut runs = read(&rotated(file))?;
+    runs.extend(read(file)?);
+    Ok(runs)
+}
+
+fn read(file: &Path) -> Result<Vec<RunRecord>> {
+    if !file.exists() {
+        return Ok(vec![]);
+    }
+    fs::read_to_string(file)
+        .with_context(|| format!("Can't read {}", file.display()))?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Can't parse {} line {}", file.display(), index + 1))
+        })
+        .collect()
+}
+
+/// Finds run by id, or by position from the end: `-1` is the last run
+pub fn find<'a>(runs: &'a [RunRecord], reference: &str) -> Result<&'a RunRecord> {
+    if let Some(back) = reference
+        .strip_prefix('-')
+        .and_then(|back| back.parse::<usize>().ok())
+    {
+        if back > 0 && back <= runs.len() {
+            return Ok(&runs[runs.len() - back]);
+        }
+    }
+    match runs.iter().find(|run| run.run == reference) {
+        Some(run) => Ok(run),
+        None => bail!("Run {} is not found in history", reference),
+    }
+}
+
+/// Changes between two runs
+#[derive(Debug, PartialEq)]
+pub struct Diff {
+    pub conf_changed: bool,
+    /// Items excluded after the second run but not after the first one
+    pub added: Vec<PathBuf>,
+    /// Items excluded after the first run but not after the second one
+    pub removed: Vec<PathBuf>,
+}
+
+pub fn diff(a: &RunRecord, b: &RunRecord) -> Result<Diff> {
+    let (before, after) = (a.exclusions()?, b.exclusions()?);
+    Ok(Diff {
+        conf_changed: a.conf_hash != b.conf_hash,
+        added: after.difference(before).cloned().collect(),
+        removed: before.difference(after).cloned().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::TestDir;
+
+    use super::*;
+
+    fn run(id: &str, new: &[&str], excluded: &[&str]) -> RunRecord {
+        let mut run = RunRecord::start(&Conf {
+            paths: vec!["/Dev".to_string()],
+            ..Conf::default()
+        })
+        .unwrap();
+        run.run = id.to_string();
+        run.count(
+            &new.iter().map(PathBuf::from).collect::<Vec<_>>(),
+            &excluded.iter().map(PathBuf::from).collect::<Vec<_>>(),
+        );
+        run
+    }
+
+    #[test]
+    fn it_records_history() {
+        let dir = TestDir::new();
+        let file = dir.join("state/history.jsonl");
+        assert!(load(&file).unwrap().is_empty());
+
+        let first = run("first", &["/Dev/a/target", "/Dev/b/dist"], &[]);
+        let second = run("second", &["/Dev/c/target"], &["/Dev/a/target"]);
+        record(&file, &first).unwrap();
+        record(&file, &second).unwrap();
+
+        let runs = load(&file).unwrap();
+        assert_eq!(runs, [first.clone(), second.clone()]);
+        assert_eq!(find(&runs, "first").unwrap(), &first);
+        assert_eq!(find(&runs, "-1").unwrap(), &second);
+        assert_eq!(find(&runs, "-2").unwrap(), &first);
+        assert!(find(&runs, "-3").is_err());
+        assert!(find(&runs, "third").is_err());
+    }
+
+    #[test]
+    fn it_rotates_history() {
+        let dir = TestDir::new();
+        let file = dir.join("history.jsonl");
+        let first = run("first", &[], &[]);
+        let second = run("second", &[], &[]);
+        record(&file, &first).unwrap();
+        fs::write(&file, " ".repeat(MAX_SIZE as usize + 1) + "\n").unwrap();
+        record(&file, &second).unwrap();
+
+        assert!(rotated(&file).exists());
+        assert_eq!(fs::read_to_string(&file).unwrap().lines().count(), 1);
+        assert_eq!(load(&file).unwrap(), [second]);
+    }
+
+    #[test]
+    fn it_hashes_configuration_stably() {
+        assert_eq!(
+            conf_hash(&Conf::default()).unwrap(),
+            conf_hash(&Conf::default()).unwrap()
+        );
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn it_diffs_runs() {
+        let first = run("first", &["/Dev/a/target", "/Dev/b/dist"], &[]);
+        let mut second = run("second", &["/Dev/c/target"], &["/Dev/a/target"]);
+
+        assert_eq!(
+            diff(&first, &second).unwrap(),
+            Diff {
+                conf_changed: false,
+                added: vec![PathBuf::from("/Dev/c/target")],
+                removed: vec![PathBuf::from("/Dev/b/dist")],
+            }
+        );
+
+        second.conf_hash = conf_hash(&Conf {
+            dry_run: true,
+            ..Conf::default()
+        })
+        .unwrap();
+        assert!(diff(&first, &second).unwrap().conf_changed);
+
+        // Too many exclusions to list them
+        let paths = (0..=MAX_LISTED)
+            .map(|index| PathBuf::from(format!("/Dev/{}/target", index)))
+            .collect::<Vec<_>>();
+        second.count(&paths, &[]);
+        assert_eq!(second.new, MAX_LISTED + 1);
+        assert!(second.exclusions.is_none());
+        assert!(diff(&first, &second).is_err());
+    }
+
+    #[test]
+    fn it_describes_aborted_runs() {
+        let mut aborted = run("aborted", &[], &["/Dev/a/target"]);
+        aborted.status = RunStatus::Aborted;
+        aborted.error = Some("too many new exclusions".to_string());
+
+        assert!(aborted
+            .describe()
+            .ends_with(": new 0, excluded 1, errors 0, aborted: too many new exclusions"));
+
+        let mut dry = run("dry", &[], &["/Dev/a/target"]);
+        dry.dry_run = true;
+        dry.planned = 2;
+        assert!(dry
+            .describe()
+            .ends_with(": new 0, excluded 1, errors 0, dry run, would exclude 2"));
+    }
+}
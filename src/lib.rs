@@ -1,36 +1,62 @@
 use std::ffi::OsStr;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::fs;
+use std::io::{self, IsTerminal};
 
 mod args;
+mod audit;
 mod conf;
+mod conf_check;
+mod conf_init;
+mod conf_layers;
 mod constants;
 mod directory_iterator;
+mod dirs;
+mod duration;
+mod expand;
+mod expiry;
+mod explain;
 mod git;
+mod guard;
+mod history;
 mod logger;
+mod matcher;
+mod migrate;
+mod owner;
+mod protect;
 mod recursive_directory_iterator;
 mod time_machine;
+mod tmbliss_file;
 
 #[cfg(test)]
 pub mod filetree;
 #[cfg(test)]
 pub mod test_utils;
 
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::path::Path;
 use std::rc::Rc;
+use std::time::SystemTime;
 use std::{cell::RefCell, path::PathBuf};
 
-use anyhow::{anyhow, Context, Result};
-use glob_match::glob_match;
+use anyhow::{bail, Context, Result};
 use recursive_directory_iterator::RecursiveDirectoryIterator;
-
-pub use crate::args::{Args, Command};
-use crate::conf::Conf;
-use crate::constants::TMBLISS_FILE;
+use serde_json::Value;
+
+pub use crate::args::{Args, Command, ConfCommand, HistoryCommand, Options};
+use crate::conf::{Conf, ConfFormat};
+use crate::conf_check::Severity;
+use crate::conf_init::Preset;
+use crate::conf_layers::ConfLayers;
+use crate::constants::{CONFIG_FILE, CONF_VERSION, EXPIRING_FILE, HISTORY_FILE, TMBLISS_FILE};
 use crate::directory_iterator::DirectoryIterator;
+use crate::explain::Explainer;
 use crate::git::Git;
+use crate::guard::{NewExclusion, Plan};
+use crate::history::{RunRecord, RunStatus};
 use crate::logger::Logger;
+use crate::matcher::Matcher;
+use crate::owner::OwnerFilter;
+pub use crate::owner::{Owner, OwnerKind};
 pub use crate::time_machine::{TimeMachine, TimeMachineError};
 
 pub struct TMBliss {}
@@ -41,72 +67,112 @@ impl TMBliss {
             Command::Run {
                 path,
                 dry_run,
-                allowlist_glob,
-                allowlist_path,
-                skip_glob,
-                skip_path,
-                skip_errors,
-                exclude_path,
+                options,
+                print0,
+                save_conf,
             } => {
-                let logger = Logger { filter: None };
-
-                Self::mark_files(
-                    Conf {
-                        paths: path,
-                        dry_run,
-                        allowlist_glob,
-                        allowlist_path,
-                        skip_glob,
-                        skip_path,
-                        skip_errors,
-                        exclude_paths: exclude_path,
-                    },
-                    &logger,
-                )
+                let logger = Logger {
+                    filter: None,
+                    print0,
+                };
+                let mut conf = Conf {
+                    paths: path,
+                    dry_run,
+                    skip_errors: true,
+                    ..Conf::default()
+                };
+                options.apply(&mut conf);
+                if let Some(save_conf) = save_conf {
+                    let path = Path::new(&save_conf);
+                    conf_init::save(&conf_init::absolute_paths(conf)?, path, None, false)?;
+                    logger.log("saved", &save_conf);
+                    return Ok(());
+                }
+                Self::mark_files_recorded(conf, options.force, &logger)
             }
             Command::List {
                 path,
-                allowlist_glob,
-                allowlist_path,
-                skip_glob,
-                skip_path,
-                skip_errors,
-                exclude_path,
+                options,
+                print0,
             } => {
-                let logger = Logger { filter: None };
-
-                Self::mark_files(
-                    Conf {
-                        paths: path,
-                        dry_run: true,
-                        allowlist_glob,
-                        allowlist_path,
-                        skip_glob,
-                        skip_path,
-                        skip_errors,
-                        exclude_paths: exclude_path,
-                    },
-                    &logger,
-                )
+                let logger = Logger {
+                    filter: None,
+                    print0,
+                };
+                let mut conf = Conf {
+                    paths: path,
+                    dry_run: true,
+                    skip_errors: true,
+                    ..Conf::default()
+                };
+                options.apply(&mut conf);
+                let mut record = RunRecord::start(&conf)?;
+                Self::mark_files(conf, options.force, &mut record, &logger)
             }
-            Command::Conf { path, dry_run } => {
-                let conf = Conf::parse(&path);
-                match conf {
-                    Ok(mut conf) => {
-                        let logger = Logger { filter: None };
+            Command::Conf {
+                path,
+                dry_run,
+                format,
+                profile,
+                options,
+                command,
+            } => {
+                let load = || ConfLayers::load(path.as_deref().map(Path::new), format);
+                let show = matches!(command, Some(ConfCommand::Show { .. }));
+                if command.is_some() && !show && options != Options::default() {
+                    bail!("Run options are only allowed in 'conf' without a subcommand or in 'conf show'");
+                }
+                match command {
+                    Some(ConfCommand::Show { resolved }) => {
+                        return Self::show_conf(&load()?, resolved, &profile, dry_run, &options)
+                    }
+                    Some(ConfCommand::Check { strict }) => {
+                        return Self::check_conf(&load()?, strict)
+                    }
+                    Some(ConfCommand::Schema) => {
+                        let schema = schemars::schema_for!(Conf);
+                        println!("{}", serde_json::to_string_pretty(&schema)?);
+                        return Ok(());
+                    }
+                    Some(ConfCommand::Init {
+                        root,
+                        preset,
+                        force,
+                    }) => return Self::init_conf(path.as_deref(), format, root, preset, force),
+                    Some(ConfCommand::Migrate { write }) => {
+                        return Self::migrate_conf(path.as_deref(), format, write)
+                    }
+                    None => {}
+                }
+                let layers = load()?;
+                let logger = Logger {
+                    filter: None,
+                    print0: false,
+                };
 
-                        if let Some(dry_run) = dry_run {
-                            conf.dry_run = dry_run;
-                        }
-                        Self::mark_files(conf, &logger)
+                for (name, mut conf) in layers.resolve()?.resolve(&profile)? {
+                    if let Some(name) = name {
+                        logger.log("profile", &name);
                     }
-                    Err(e) => Err(e),
+                    if let Some(dry_run) = dry_run {
+                        conf.dry_run = dry_run;
+                    }
+                    options.apply(&mut conf);
+                    Self::mark_files_recorded(conf, options.force, &logger)?;
                 }
+                Ok(())
             }
-            Command::Service { path, dry_run } => {
-                let conf = Conf::parse(&path);
+            Command::Service {
+                path,
+                dry_run,
+                format,
+                profile,
+                options,
+            } => {
+                let conf = ConfLayers::load(path.as_deref().map(Path::new), format)
+                    .and_then(|layers| layers.resolve());
                 match conf {
-                    Ok(mut conf) => {
+                    Ok(conf) => {
                         let filter = |label: &str, _message: &str| {
                             if label == "excluded" {
                                 return true;
@@ -115,42 +181,127 @@ impl TMBliss {
                         };
                         let logger = Logger {
                             filter: Some(&filter),
+                            print0: false,
                         };
-                        if let Some(dry_run) = dry_run {
-                            conf.dry_run = dry_run;
-                        }
                         logger.log("started", &chrono::Local::now().to_string());
-                        logger.log("dry run", &conf.dry_run.to_string());
-                        Self::mark_files(conf, &logger)?;
+                        for (name, mut conf) in conf.resolve(&profile)? {
+                            if let Some(name) = name {
+                                logger.log("profile", &name);
+                            }
+                            if let Some(dry_run) = dry_run {
+                                conf.dry_run = dry_run;
+                            }
+                            options.apply(&mut conf);
+                            logger.log("dry run", &conf.dry_run.to_string());
+                            Self::mark_files_recorded(conf, options.force, &logger)?;
+                        }
                         logger.log("ended", &chrono::Local::now().to_string());
                         Ok(())
                     }
                     Err(e) => Err(e),
                 }
             }
+            Command::Exclude { path, ttl } => {
+                let paths = path.iter().map(PathBuf::from).collect::<Vec<_>>();
+                let file = Self::expiring_file().context("State directory is unknown")?;
+                expiry::exclude(&paths, ttl, &file, chrono::Utc::now())?;
+                let logger = Logger {
+                    filter: None,
+                    print0: false,
+                };
+                for path in path {
+                    logger.log("excluded", &path);
+                }
+                Ok(())
+            }
             Command::Reset {
                 path,
                 dry_run,
                 allowlist_glob,
                 allowlist_path,
+                only_managed,
+                older_than,
             } => Self::reset_files(
                 Path::new(&path),
                 dry_run,
                 allowlist_glob,
                 allowlist_path,
-                &Logger { filter: None },
+                &OwnerFilter {
+                    managed: only_managed.then_some(true),
+                    older_than,
+                },
+                &Logger {
+                    filter: None,
+                    print0: false,
+                },
             ),
             Command::ShowExcluded {
                 path,
                 allowlist_glob,
                 allowlist_path,
+                managed,
+                manual,
+                older_than,
             } => Self::reset_files(
                 Path::new(&path),
                 true,
                 allowlist_glob,
                 allowlist_path,
-                &Logger { filter: None },
+                &OwnerFilter {
+                    managed: (managed || manual).then_some(managed),
+                    older_than,
+                },
+                &Logger {
+                    filter: None,
+                    print0: false,
+                },
             ),
+            Command::Audit { path, fix } => {
+                let findings = audit::audit(Path::new(&path), fix)?;
+                println!("{}", serde_json::to_string_pretty(&findings)?);
+                Ok(())
+            }
+            Command::Explain { path, conf } => {
+                let explainer = Self::explainer(conf)?;
+                explainer.explain(Path::new(&path))?.log(&Logger {
+                    filter: None,
+                    print0: false,
+                });
+                Ok(())
+            }
+            Command::Check {
+                path,
+                stdin,
+                zero,
+                conf,
+            } => {
+                let explainer = Self::explainer(conf)?;
+                let stdout = io::stdout();
+                if stdin {
+                    return explainer.check(io::stdin().lock(), stdout.lock(), zero);
+                }
+                let delimiter = if zero { "\0" } else { "\n" };
+                let paths = path.join(delimiter) + delimiter;
+                explainer.check(paths.as_bytes(), stdout.lock(), zero)
+            }
+            Command::History { limit, command } => {
+                let file = dirs::state_dir()
+                    .context("State directory is unknown")?
+                    .join(HISTORY_FILE);
+                let runs = history::load(&file)?;
+                match command {
+                    Some(HistoryCommand::Diff { a, b }) => {
+                        Self::diff_runs(history::find(&runs, &a)?, history::find(&runs, &b)?)?
+                    }
+                    None => {
+                        let skip = runs.len().saturating_sub(limit.unwrap_or(runs.len()));
+                        for run in &runs[skip..] {
+                            println!("{}", run.describe());
+                        }
+                    }
+                }
+                Ok(())
+            }
             Command::MarkdownHelp => {
                 clap_markdown::print_help_markdown::<Args>();
                 Ok(())
@@ -158,42 +309,354 @@ impl TMBliss {
         }
     }
 
-    fn mark_files(conf: Conf, logger: &Logger) -> Result<()> {
-        let processed: Rc<RefCell<HashSet<PathBuf>>> = Rc::new(RefCell::new(HashSet::new()));
+    fn show_conf(
+        layers: &ConfLayers,
+        resolved: bool,
+        profile: &[String],
+        dry_run: Option<bool>,
+        options: &Options,
+    ) -> Result<()> {
+        if !resolved {
+            for layer in &layers.layers {
+                println!("# {}", layer.source);
+                println!("{}", serde_json::to_string_pretty(&layer.options)?);
+            }
+            return Ok(());
+        }
+        for (name, mut conf) in layers.resolve()?.resolve(profile)? {
+            if let Some(name) = name {
+                println!("# profile {}", name);
+            }
+            if let Some(dry_run) = dry_run {
+                conf.dry_run = dry_run;
+            }
+            options.apply(&mut conf);
+            println!("{}", serde_json::to_string_pretty(&conf)?);
+        }
+        Ok(())
+    }
+
+    /// Prints configuration issues. Fails on errors, and on warnings too if `strict`
+    fn check_conf(layers: &ConfLayers, strict: bool) -> Result<()> {
+        let logger = Logger {
+            filter: None,
+            print0: false,
+        };
+        let issues = conf_check::check(layers);
+        for issue in &issues {
+            logger.log(&issue.severity.to_string(), &issue.message);
+        }
+        let errors = issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Error)
+            .count();
+        if errors > 0 {
+            bail!("Configuration has {} errors", errors);
+        }
+        if strict && !issues.is_empty() {
+            bail!("Configuration has {} warnings", issues.len());
+        }
+        match issues.is_empty() {
+            true => logger.log("ok", "configuration is valid"),
+            false => logger.log(
+                "ok",
+                &format!("configuration is valid, {} warnings", issues.len()),
+            ),
+        }
+        Ok(())
+    }
+
+    /// Writes starter configuration, asks for roots and presets if none are given
+    /// and standard input is a terminal
+    fn init_conf(
+        path: Option<&str>,
+        format: Option<ConfFormat>,
+        root: Vec<String>,
+        preset: Vec<Preset>,
+        force: bool,
+    ) -> Result<()> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => dirs::config_dir()
+                .context("Configuration directory is unknown. Pass --path")?
+                .join(CONFIG_FILE)
+                .with_extension("jsonc"),
+        };
+        let (root, preset) = if root.is_empty() && preset.is_empty() && io::stdin().is_terminal() {
+            let home = dirs::home_dir().context("Home directory is unknown")?;
+            conf_init::ask(io::stdin().lock(), io::stdout(), &home)?
+        } else {
+            (root, preset)
+        };
+        conf_init::save(&conf_init::starter(root, &preset), &path, format, force)?;
+        Logger {
+            filter: None,
+            print0: false,
+        }
+        .log("saved", &path.to_string_lossy());
+        Ok(())
+    }
+
+    /// Upgrades configuration file to the current version. Prints migrated configuration,
+    /// or replaces the file keeping the original with `.bak` suffix
+    fn migrate_conf(path: Option<&str>, format: Option<ConfFormat>, write: bool) -> Result<()> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => ConfLayers::discover().context("Configuration not found. Pass --path")?,
+        };
+        let format = format.unwrap_or_else(|| ConfFormat::from_path(&path));
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Cannot open configuration at {}", path.display()))?;
+        let Value::Object(mut options) = format
+            .deserialize(&content)
+            .with_context(|| format!("Cannot parse configuration at {}", path.display()))?
+        else {
+            bail!("Configuration at {} is not a map", path.display());
+        };
+        let version = migrate::version(&options)?;
+        let changes = migrate::migrate(&mut options)
+            .with_context(|| format!("Cannot migrate configuration at {}", path.display()))?;
+        let migrated = format.serialize(&options)?;
+        if !write {
+            print!("{}", migrated);
+            return Ok(());
+        }
+
+        let logger = Logger {
+            filter: None,
+            print0: false,
+        };
+        if version == CONF_VERSION {
+            logger.log("up to date", &path.to_string_lossy());
+            return Ok(());
+        }
+        for change in &changes {
+            logger.log("migrated", change);
+        }
+        logger.log("version", &format!("{} -> {}", version, CONF_VERSION));
+        let mut backup = path.clone().into_os_string();
+        backup.push(".bak");
+        fs::copy(&path, &backup)
+            .with_context(|| format!("Cannot back up configuration at {}", path.display()))?;
+        logger.log("backup", &backup.to_string_lossy());
+        fs::write(&path, migrated)
+            .with_context(|| format!("Cannot write configuration at {}", path.display()))?;
+        logger.log("saved", &path.to_string_lossy());
+        Ok(())
+    }
+
+    fn explainer(conf: Option<String>) -> Result<Explainer> {
+        let conf = match conf {
+            Some(conf) => Conf::parse(&conf)?,
+            None => Conf::default(),
+        };
+        Explainer::new(conf)
+    }
+
+    /// Marks files and records the run in history, whether it completes,
+    /// is aborted by safety guards or fails
+    fn mark_files_recorded(conf: Conf, force: bool, logger: &Logger) -> Result<()> {
+        let mut record = RunRecord::start(&conf)?;
+        let result = Self::mark_files(conf, force, &mut record, logger);
+        record.ended = chrono::Utc::now();
+        if let Err(e) = &result {
+            if record.status == RunStatus::Completed {
+                record.status = RunStatus::Error;
+            }
+            record.error = Some(format!("{:#}", e));
+        }
+        Self::record_history(&record, logger);
+        result
+    }
+
+    fn mark_files(
+        mut conf: Conf,
+        force: bool,
+        record: &mut RunRecord,
+        logger: &Logger,
+    ) -> Result<()> {
+        if let Some(file) = Self::expiring_file() {
+            for path in expiry::lift_expired(&file, chrono::Utc::now(), conf.dry_run, logger)? {
+                logger.log("expired", &path.to_string_lossy());
+            }
+        }
+
+        conf.read_lists()?;
+        record.roots.clone_from(&conf.paths);
+        let matchers = conf
+            .paths
+            .iter()
+            .map(|path| Matcher::new(&conf, Path::new(path)))
+            .collect::<Result<Vec<_>>>()?;
+        let plan: Rc<RefCell<Plan>> = Rc::new(RefCell::new(Plan::default()));
 
         for item in conf.exclude_paths.clone() {
-            Self::process(Path::new(&item), &conf, processed.clone(), logger)?;
+            let item = Path::new(&item);
+            if !item.exists() {
+                if conf.missing_ok {
+                    logger.log("missing", &item.to_string_lossy());
+                    continue;
+                }
+                if item.to_string_lossy().contains(['*', '?', '[']) {
+                    bail!("exclude_paths glob `{}` matches nothing", item.display());
+                }
+            }
+            Self::process(
+                item,
+                &conf,
+                plan.clone(),
+                OwnerKind::ExcludePaths,
+                || "exclude_paths".to_string(),
+                logger,
+            )?;
+        }
+
+        for (path, matcher) in conf.paths.iter().zip(matchers) {
+            // core.ignoreCase is read once per root and nested repository,
+            // not for every directory
+            let ignore_case = Git::ignore_case(Path::new(path));
+            Self::process_directory(
+                Path::new(path),
+                &conf,
+                &matcher,
+                ignore_case,
+                plan.clone(),
+                logger,
+            )?;
         }
 
-        for path in &conf.paths {
-            Self::process_directory(Path::new(path), &conf, processed.clone(), logger)?;
+        // Nothing is written until the whole run is planned and passes guards
+        let plan = plan.take();
+        let reasons = guard::check(&plan, &conf);
+        for reason in &reasons {
+            logger.log("guard", reason);
+        }
+        record.errors = plan.errors.len();
+        record.bytes = conf
+            .history_bytes
+            .then(|| plan.new.iter().map(|item| guard::size(&item.path)).sum());
+        if conf.dry_run {
+            record.planned = plan.new.len();
+            record.count(&[], &plan.excluded);
+            return Ok(());
+        }
+        if !reasons.is_empty() && !force {
+            record.count(&[], &plan.excluded);
+            record.status = RunStatus::Aborted;
+            bail!(
+                "Aborted before excluding anything: {}. Use --force to exclude anyway",
+                reasons.join("; ")
+            );
         }
 
+        let time = chrono::Utc::now();
+        let mut new = vec![];
+        for item in plan.new {
+            let owner = Owner {
+                run: record.run.clone(),
+                time,
+                kind: item.kind,
+                reason: item.reason,
+                expires: None,
+            };
+            match TimeMachine::add_exclusion(&item.path, Some(&owner)) {
+                Ok(_) => new.push(item.path),
+                Err(e) => {
+                    if conf.skip_errors {
+                        logger.log(
+                            "error_excluding",
+                            &[item.path.to_string_lossy().as_ref(), &e.to_string()].join(", "),
+                        );
+                        record.errors += 1;
+                    } else {
+                        record.count(&new, &plan.excluded);
+                        return Err(e.into());
+                    }
+                }
+            }
+        }
+
+        record.count(&new, &plan.excluded);
+        Ok(())
+    }
+
+    fn diff_runs(a: &RunRecord, b: &RunRecord) -> Result<()> {
+        let logger = Logger {
+            filter: None,
+            print0: false,
+        };
+        let diff = history::diff(a, b)?;
+        logger.log("runs", &format!("{} -> {}", a.run, b.run));
+        logger.log(
+            "conf",
+            if diff.conf_changed {
+                "changed"
+            } else {
+                "unchanged"
+            },
+        );
+        for path in diff.added {
+            logger.log("added", &path.to_string_lossy());
+        }
+        for path in diff.removed {
+            logger.log("removed", &path.to_string_lossy());
+        }
         Ok(())
     }
 
+    /// Appends the run to history in the state directory. History is secondary,
+    /// so the run doesn't fail if it can't be written
+    fn record_history(record: &RunRecord, logger: &Logger) {
+        let Some(file) = dirs::state_dir().map(|dir| dir.join(HISTORY_FILE)) else {
+            return;
+        };
+        if let Err(e) = history::record(&file, record) {
+            logger.log("warning", &format!("Can't record run history: {:#}", e));
+        }
+    }
+
+    /// Index of expiring exclusions in the state directory
+    fn expiring_file() -> Option<PathBuf> {
+        dirs::state_dir().map(|dir| dir.join(EXPIRING_FILE))
+    }
+
     fn reset_files(
         path: &Path,
         dry_run: bool,
         allowlist_glob: Vec<String>,
         allowlist_path: Vec<String>,
+        filter: &OwnerFilter,
         logger: &Logger,
     ) -> Result<()> {
+        let matcher = Matcher::new(
+            &Conf {
+                allowlist_glob,
+                allowlist_path,
+                // Allowlisted exclusions aren't reset, built-in list must not keep them
+                disable_builtin_allowlist: true,
+                ..Conf::default()
+            },
+            path,
+        )?;
+        let now = chrono::Utc::now();
         let iterator = RecursiveDirectoryIterator {
             path,
             op: &|path| {
-                for exclusion in &allowlist_path {
-                    if Self::is_inside(Path::new(exclusion), path) {
-                        return Ok(true);
-                    }
+                if matcher.is_allowlisted(path) {
+                    return Ok(true);
                 }
-                for exclusion in &allowlist_glob {
-                    if glob_match(exclusion, &path.to_string_lossy()) {
-                        return Ok(true);
-                    }
+                if !TimeMachine::is_excluded(path)? {
+                    return Ok(true);
                 }
-                if TimeMachine::is_excluded(path)? {
-                    logger.log("excluded", &path.to_string_lossy());
+                let owner = TimeMachine::owner(path)?;
+                if filter.matches(owner.as_ref(), now) {
+                    match owner {
+                        Some(owner) => logger.log(
+                            "excluded",
+                            &format!("{} ({})", path.to_string_lossy(), owner.describe(now)),
+                        ),
+                        None => logger.log("excluded", &path.to_string_lossy()),
+                    }
                     if !dry_run {
                         TimeMachine::remove_exclusion(path)?
                     }
@@ -208,24 +671,27 @@ impl TMBliss {
     fn process(
         item: &Path,
         conf: &Conf,
-        processed: Rc<RefCell<HashSet<PathBuf>>>,
+        plan: Rc<RefCell<Plan>>,
+        kind: OwnerKind,
+        reason: impl FnOnce() -> String,
         logger: &Logger,
     ) -> Result<()> {
         let item = item
             .canonicalize()
             .with_context(|| format!("Can't canonicalize path {}", item.display()))?;
         let item = &item;
-        if processed.borrow().contains(item) {
+        if plan.borrow().processed.contains(item) {
             return Ok(());
         }
 
-        processed.borrow_mut().insert(item.to_owned());
+        plan.borrow_mut().processed.insert(item.to_owned());
 
         let check_result = TimeMachine::is_excluded(item);
         match check_result {
             Ok(is_excluded) => {
                 if is_excluded {
                     logger.log("excluded", &item.to_string_lossy());
+                    plan.borrow_mut().excluded.push(item.to_owned());
                     return Ok(());
                 } else {
                     logger.log("new", &item.to_string_lossy());
@@ -237,6 +703,7 @@ impl TMBliss {
                         "error_checking",
                         &[item.to_string_lossy().as_ref(), &e.to_string()].join(", "),
                     );
+                    plan.borrow_mut().errors.push(item.to_owned());
                     return Ok(());
                 } else {
                     return Err(e.into());
@@ -244,22 +711,11 @@ impl TMBliss {
             }
         }
 
-        if !conf.dry_run {
-            let result = TimeMachine::add_exclusion(item);
-            match result {
-                Ok(_) => {}
-                Err(e) => {
-                    if conf.skip_errors {
-                        logger.log(
-                            "error_excluding",
-                            &[item.to_string_lossy().as_ref(), &e.to_string()].join(", "),
-                        );
-                    } else {
-                        return Err(e.into());
-                    }
-                }
-            }
-        }
+        plan.borrow_mut().new.push(NewExclusion {
+            path: item.to_owned(),
+            kind,
+            reason: reason(),
+        });
 
         Ok(())
     }
@@ -267,73 +723,62 @@ impl TMBliss {
     fn process_directory(
         path: &Path,
         conf: &Conf,
-        processed: Rc<RefCell<HashSet<PathBuf>>>,
+        matcher: &Matcher,
+        ignore_case: bool,
+        plan: Rc<RefCell<Plan>>,
         logger: &Logger,
     ) -> Result<()> {
         let path = Path::new(path)
             .canonicalize()
             .with_context(|| format!("Can't canonicalize path {}", path.display()))?;
         let path = &path;
-        // Gather .tmbliss globs for this directory
-        let mut newconf = conf.clone();
-        let mut effective_skip_glob = conf.skip_glob.clone();
-        let tmbliss_globs = Self::read_tmbliss_globs(path);
-        let tmbliss_globs = tmbliss_globs
-            .iter()
-            .map(|s| -> Result<String> {
-                let stripped = if s.starts_with("/") {
-                    s.strip_prefix("/")
-                        .ok_or_else(|| anyhow!("Failed to strip prefix from {}", s))?
-                } else {
-                    s.as_str()
-                };
-                Ok(path.join(stripped).to_string_lossy().to_string())
-            })
-            .collect::<Result<Vec<String>>>()?;
-        effective_skip_glob.extend(tmbliss_globs);
-        newconf.skip_glob = effective_skip_glob.clone();
-
-        // Excluder closure that uses effective_skip_glob
-        let excluder = |item: &Path| -> bool {
+        let matcher = &matcher
+            .with_tmbliss_file(path)
+            .with_context(|| format!("Can't read .tmbliss in {}", path.display()))?;
+
+        // Excluder closure that uses effective skip rules
+        let excluder = |item: &Path, matcher: &Matcher| -> bool {
             if item.is_file() && item.file_name() == Some(OsStr::new(TMBLISS_FILE)) {
                 return true;
             }
             if item.is_file() && item.file_name() == Some(OsStr::new(".gitignore")) {
                 return true;
             }
-            if processed.borrow().contains(item) {
+            if plan.borrow().processed.contains(item) {
                 return true;
             }
             if Git::is_git(item) {
-                processed.borrow_mut().insert(item.to_path_buf());
+                plan.borrow_mut().processed.insert(item.to_path_buf());
                 return true;
             }
-            for exclusion in &effective_skip_glob {
-                if glob_match(exclusion, &item.to_string_lossy()) {
-                    processed.borrow_mut().insert(item.to_path_buf());
-                    return true;
-                }
-            }
-            for exclusion in &conf.skip_path {
-                if Self::is_inside(Path::new(exclusion), item) {
-                    processed.borrow_mut().insert(item.to_path_buf());
-                    return true;
-                }
+            if matcher.is_skipped(item) {
+                plan.borrow_mut().processed.insert(item.to_path_buf());
+                return true;
             }
             false
         };
 
-        if excluder(path) {
+        if excluder(path, matcher) {
             return Ok(());
         }
 
         if TimeMachine::is_excluded(path)? {
-            Self::process(path, &newconf, processed, logger)
+            // Already excluded, so no new exclusion needs a reason
+            Self::process(path, conf, plan, OwnerKind::Gitignore, String::new, logger)
                 .with_context(|| format!("Can't process path {}", path.display()))?;
             return Ok(());
         }
 
-        let excludes = Self::get_git_excludes(path, &newconf);
+        // Nested repositories have their own core.ignoreCase
+        let ignore_case = match path.join(".git").exists() {
+            true => Git::ignore_case(path),
+            false => ignore_case,
+        };
+        let git = Git {
+            path: path.to_path_buf(),
+            ignore_case,
+        };
+        let excludes = git.get_ignores_list().unwrap_or_default();
 
         let parents = |item: &Path| -> Vec<PathBuf> {
             let mut out: Vec<PathBuf> = vec![];
@@ -350,105 +795,194 @@ impl TMBliss {
             out
         };
 
+        // Ignored items may be deep inside the directory, so they are checked
+        // with .tmbliss files of all directories in between
+        let mut nested_matchers: HashMap<PathBuf, Matcher> = HashMap::new();
         for item in excludes.clone() {
-            let excluded = excluder(&item) || parents(&item).iter().any(|p| excluder(p));
+            let Some(dir) = item.parent() else {
+                continue;
+            };
+            if !nested_matchers.contains_key(dir) {
+                let nested_matcher = Self::nested_matcher(matcher, path, dir)?;
+                nested_matchers.insert(dir.to_path_buf(), nested_matcher);
+            }
+            let item_matcher = &nested_matchers[dir];
+            let excluded = item_matcher.is_allowlisted(&item)
+                || excluder(&item, item_matcher)
+                || parents(&item).iter().any(|p| excluder(p, item_matcher));
             if excluded {
                 continue;
             };
-            Self::process(Path::new(&item), &newconf, processed.clone(), logger).with_context(
-                || {
-                    format!(
-                        "Can't process paths {}",
-                        excludes
-                            .iter()
-                            .map(|p| p.to_string_lossy())
-                            .collect::<Vec<_>>()
-                            .join(", ")
-                    )
-                },
-            )?;
+            let is_protected = |entry: &Path| item_matcher.is_allowlisted(entry);
+            if item.is_dir() && protect::holds_protected(&item, is_protected) {
+                if TimeMachine::is_excluded(&item).unwrap_or(false) {
+                    logger.log(
+                        "warning",
+                        &format!(
+                            "{} is excluded along with protected items inside, reset it to keep them in backup",
+                            item.display()
+                        ),
+                    );
+                } else {
+                    logger.log(
+                        "protected",
+                        &format!(
+                            "{} holds protected items, excluding the rest of it",
+                            item.display()
+                        ),
+                    );
+                    Self::process_around_protected(
+                        &item,
+                        conf,
+                        item_matcher,
+                        &git,
+                        plan.clone(),
+                        &excluder,
+                        logger,
+                    )?;
+                    continue;
+                }
+            }
+            if (conf.dry_run || conf.warn_risky) && !TimeMachine::is_excluded(&item).unwrap_or(true)
+            {
+                if let Some(warning) = protect::risky_files(&item, SystemTime::now()).describe() {
+                    logger.log("warning", &warning);
+                }
+            }
+            let reason = || Self::gitignore_reason(&git, &item);
+            Self::process(
+                Path::new(&item),
+                conf,
+                plan.clone(),
+                OwnerKind::Gitignore,
+                reason,
+                logger,
+            )
+            .with_context(|| {
+                format!(
+                    "Can't process paths {}",
+                    excludes
+                        .iter()
+                        .map(|p| p.to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?;
+        }
+
+        // Force excluded items are checked level by level, as children of excluded
+        // directories are never visited
+        for entry in
+            fs::read_dir(path).with_context(|| format!("Can't read dir {}", path.display()))?
+        {
+            let item = entry?.path();
+            if !matcher.is_force_excluded(&item) || excluder(&item, matcher) {
+                continue;
+            }
+            let reason = || {
+                matcher
+                    .decide(&item)
+                    .map(|rule| rule.to_string())
+                    .unwrap_or_default()
+            };
+            Self::process(&item, conf, plan.clone(), OwnerKind::Rule, reason, logger)
+                .with_context(|| format!("Can't process path {}", item.display()))?;
         }
 
         let directory_iterator = DirectoryIterator {
             path,
-            exclude: Some(&excluder),
+            exclude: Some(&|item| excluder(item, matcher)),
         };
         let directories = directory_iterator
             .list()
             .with_context(|| format!("Can't list directory {}", path.display()))?;
 
         for path in directories {
-            // Recurse, passing down the new effective_skip_glob
-            Self::process_directory(&path, &newconf, processed.clone(), logger)
+            // Recurse, passing down matcher with .tmbliss files
+            Self::process_directory(&path, conf, matcher, ignore_case, plan.clone(), logger)
                 .with_context(|| format!("Can't process directory {}", path.display()))?;
         }
 
         Ok(())
     }
 
-    fn get_git_excludes(path: &Path, conf: &Conf) -> Vec<PathBuf> {
-        let git = Git {
-            path: path.to_path_buf(),
-        };
-        git.get_ignores_list()
-            .unwrap_or_default()
-            .into_iter()
-            .filter(|item| {
-                for exclusion in &conf.skip_path {
-                    if Self::is_inside(Path::new(exclusion), item) {
-                        return false;
-                    }
-                }
-                for exclusion in &conf.skip_glob {
-                    if glob_match(exclusion, &item.to_string_lossy()) {
-                        return false;
-                    }
-                }
-                for exclusion in &conf.allowlist_path {
-                    if Self::is_inside(Path::new(exclusion), item) {
-                        return false;
-                    }
-                }
-                for exclusion in &conf.allowlist_glob {
-                    if glob_match(exclusion, &item.to_string_lossy()) {
-                        return false;
-                    }
-                }
-                true
-            })
-            .collect()
+    /// Excludes contents of an ignored directory except protected items, so a secret
+    /// stays in backup while the rest of the directory doesn't. Subdirectories that
+    /// hold protected items are processed the same way
+    fn process_around_protected(
+        dir: &Path,
+        conf: &Conf,
+        matcher: &Matcher,
+        git: &Git,
+        plan: Rc<RefCell<Plan>>,
+        excluder: &dyn Fn(&Path, &Matcher) -> bool,
+        logger: &Logger,
+    ) -> Result<()> {
+        // The directory itself is done, so it's not walked again as a regular one
+        plan.borrow_mut().processed.insert(dir.to_path_buf());
+        let matcher = &matcher
+            .with_tmbliss_file(dir)
+            .with_context(|| format!("Can't read .tmbliss in {}", dir.display()))?;
+        for entry in
+            fs::read_dir(dir).with_context(|| format!("Can't read dir {}", dir.display()))?
+        {
+            let item = entry?.path();
+            if matcher.is_allowlisted(&item) || excluder(&item, matcher) {
+                continue;
+            }
+            if item.is_dir()
+                && protect::holds_protected(&item, |entry| matcher.is_allowlisted(entry))
+            {
+                Self::process_around_protected(
+                    &item,
+                    conf,
+                    matcher,
+                    git,
+                    plan.clone(),
+                    excluder,
+                    logger,
+                )?;
+                continue;
+            }
+            let reason = || Self::gitignore_reason(git, &item);
+            Self::process(
+                &item,
+                conf,
+                plan.clone(),
+                OwnerKind::Gitignore,
+                reason,
+                logger,
+            )
+            .with_context(|| format!("Can't process path {}", item.display()))?;
+        }
+        Ok(())
     }
 
-    fn is_inside(root: &Path, child: &Path) -> bool {
-        let root = root
-            .canonicalize()
-            .unwrap_or_else(|_| Path::new(root).to_path_buf());
-        let child = child
-            .canonicalize()
-            .unwrap_or_else(|_| Path::new(child).to_path_buf());
-
-        root.eq(&child) || (child.starts_with(&root) && !root.starts_with(&child))
+    /// Describes .gitignore line that makes git ignore the item
+    fn gitignore_reason(git: &Git, item: &Path) -> String {
+        match git.match_path(item) {
+            Ok(Some(found)) => match (found.file, found.line) {
+                (Some(file), Some(line)) => {
+                    format!("gitignore: {}:{} {}", file.display(), line, found.pattern)
+                }
+                _ => format!("gitignore: {}", found.pattern),
+            },
+            _ => "gitignore".to_string(),
+        }
     }
 
-    // Reads .tmbliss file in the given directory and returns a Vec of globs (ignores comments and empty lines)
-    fn read_tmbliss_globs(dir: &Path) -> Vec<String> {
-        let tmbliss_path = dir.join(TMBLISS_FILE);
-        let file = File::open(&tmbliss_path);
-        if let Ok(file) = file {
-            let reader = BufReader::new(file);
-            reader
-                .lines()
-                .filter_map(|line| {
-                    let line = line.ok()?.trim().to_string();
-                    if line.is_empty() || line.starts_with('#') {
-                        None
-                    } else {
-                        Some(line)
-                    }
-                })
-                .collect()
-        } else {
-            Vec::new()
-        }
+    /// Extends matcher of the base directory with .tmbliss files
+    /// of directories from base (exclusive) to dir (inclusive)
+    fn nested_matcher(matcher: &Matcher, base: &Path, dir: &Path) -> Result<Matcher> {
+        let mut dirs = dir
+            .ancestors()
+            .take_while(|ancestor| *ancestor != base && ancestor.starts_with(base))
+            .collect::<Vec<_>>();
+        dirs.reverse();
+        dirs.iter().try_fold(matcher.clone(), |matcher, dir| {
+            matcher
+                .with_tmbliss_file(dir)
+                .with_context(|| format!("Can't read .tmbliss in {}", dir.display()))
+        })
     }
 }
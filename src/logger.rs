@@ -2,6 +2,8 @@ pub type LoggerFilter = dyn Fn(&str, &str) -> bool;
 
 pub struct Logger<'a> {
     pub filter: Option<&'a LoggerFilter>,
+    /// Print only matched paths terminated by NUL, other messages go to stderr
+    pub print0: bool,
 }
 
 impl Logger<'_> {
@@ -11,6 +13,12 @@ impl Logger<'_> {
                 return;
             }
         }
-        println!("{}: {}", label, message);
+        if !self.print0 {
+            println!("{}: {}", label, message);
+        } else if label == "new" || label == "excluded" {
+            print!("{}\0", message);
+        } else {
+            eprintln!("{}: {}", label, message);
+        }
     }
 }
@@ -0,0 +1,612 @@
+use std::{
+    borrow::Cow,
+    fmt::Display,
+    os::unix::fs::MetadataExt,
+    path::{Component, Path},
+};
+
+use anyhow::{Context, Result};
+use glob_match::glob_match;
+use ignore::Match;
+use regex::{Regex, RegexBuilder};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+use crate::conf::{Conf, Pattern, RuleAction, RuleConf};
+use crate::protect::BUILTIN_ALLOWLIST;
+use crate::tmbliss_file::TmblissFile;
+
+/// `glob_match` doesn't match globs with braces nested deeper than this
+const MAX_BRACES: usize = 10;
+
+/// Compiled rule of the pipeline
+#[derive(Clone)]
+pub struct Rule {
+    pub action: RuleAction,
+    pub pattern: Pattern,
+    /// Where the rule comes from, e.g. `skip_glob` or `rules[2]`
+    pub source: String,
+    regex: Option<Regex>,
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (kind, pattern) = match &self.pattern {
+            Pattern::Glob(glob) => ("glob", glob),
+            Pattern::Path(path) => ("path", path),
+            Pattern::Regex(regex) => ("regex", regex),
+        };
+        write!(f, "{}: {} {} {}", self.source, self.action, kind, pattern)
+    }
+}
+
+/// Ordered allowlist, skip and exclude rules compiled once per run.
+/// The last rule that matches a path wins, as in .gitignore.
+/// .tmbliss files take precedence over rules, the deepest file first
+/// and the user-global file last.
+/// Paths and patterns are compared in NFC form, and lowercased
+/// if matcher is case insensitive.
+///
+/// Globs are relative to the root (as in .gitignore) unless they start
+/// with `/` or `**`, or `absolute_globs` option is set
+#[derive(Clone)]
+pub struct Matcher {
+    case_insensitive: bool,
+    absolute_globs: bool,
+    root: String,
+    rules: Vec<Rule>,
+    tmbliss: Vec<TmblissFile>,
+}
+
+impl Matcher {
+    /// Creates matcher for the given root directory. Case sensitivity is taken
+    /// from configuration or detected from the volume root resides on.
+    ///
+    /// Flat options are compiled into rules in order of their precedence:
+    /// built-in allowlist, allowlist, then exclude, then skip, followed by `rules` entries
+    pub fn new(conf: &Conf, root: &Path) -> Result<Self> {
+        let case_insensitive = conf
+            .case_insensitive
+            .unwrap_or_else(|| Self::is_volume_case_insensitive(root));
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+
+        let mut matcher = Self {
+            case_insensitive,
+            absolute_globs: conf.absolute_globs,
+            root: Self::normalize(&root.to_string_lossy(), case_insensitive).into_owned(),
+            rules: vec![],
+            tmbliss: TmblissFile::read_global(
+                conf.tmbliss_file.as_deref().map(Path::new),
+                &root,
+                case_insensitive,
+            )?
+            .into_iter()
+            .collect(),
+        };
+
+        if !conf.disable_builtin_allowlist {
+            for glob in BUILTIN_ALLOWLIST {
+                matcher.push(
+                    RuleAction::Allow,
+                    Pattern::Glob(glob.to_string()),
+                    "builtin_allowlist".to_string(),
+                )?;
+            }
+        }
+
+        let flat = [
+            (
+                RuleAction::Allow,
+                "allowlist_path",
+                &conf.allowlist_path,
+                Pattern::Path as fn(String) -> Pattern,
+            ),
+            (
+                RuleAction::Allow,
+                "allowlist_glob",
+                &conf.allowlist_glob,
+                Pattern::Glob,
+            ),
+            (
+                RuleAction::Allow,
+                "allowlist_regex",
+                &conf.allowlist_regex,
+                Pattern::Regex,
+            ),
+            (
+                RuleAction::Exclude,
+                "exclude_glob",
+                &conf.exclude_glob,
+                Pattern::Glob,
+            ),
+            (
+                RuleAction::Exclude,
+                "exclude_regex",
+                &conf.exclude_regex,
+                Pattern::Regex,
+            ),
+            (
+                RuleAction::Skip,
+                "skip_path",
+                &conf.skip_path,
+                Pattern::Path,
+            ),
+            (
+                RuleAction::Skip,
+                "skip_glob",
+                &conf.skip_glob,
+                Pattern::Glob,
+            ),
+            (
+                RuleAction::Skip,
+                "skip_regex",
+                &conf.skip_regex,
+                Pattern::Regex,
+            ),
+        ];
+        for (action, source, patterns, pattern) in flat {
+            for item in patterns {
+                matcher.push(action, pattern(item.clone()), source.to_string())?;
+            }
+        }
+        for (index, RuleConf { action, pattern }) in conf.rules.iter().enumerate() {
+            matcher.push(*action, pattern.clone(), format!("rules[{}]", index))?;
+        }
+
+        Ok(matcher)
+    }
+
+    /// Returns a copy of the matcher with .tmbliss file of the given directory added
+    pub fn with_tmbliss_file(&self, dir: &Path) -> Result<Self> {
+        let mut matcher = self.clone();
+        if let Some(file) = TmblissFile::read(dir, self.case_insensitive)? {
+            matcher.tmbliss.push(file);
+        }
+        Ok(matcher)
+    }
+
+    /// Returns the rule that decides what to do with the path: pattern of
+    /// the deepest .tmbliss file that matches, otherwise the last matching rule
+    pub fn decide(&self, path: &Path) -> Option<Rule> {
+        for file in self.tmbliss.iter().rev() {
+            match file.matched(path) {
+                Match::None => continue,
+                Match::Ignore((action, glob)) => {
+                    return Some(Rule {
+                        action,
+                        pattern: Pattern::Glob(glob),
+                        source: file.path.to_string_lossy().into_owned(),
+                        regex: None,
+                    })
+                }
+                Match::Whitelist(_) => break,
+            }
+        }
+
+        let pathstr = Self::normalize(&path.to_string_lossy(), false).into_owned();
+        let normalized = Self::normalize(&pathstr, self.case_insensitive);
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| match &rule.pattern {
+                Pattern::Glob(glob) => self.glob_matches(glob, path, &normalized),
+                Pattern::Path(root) => self.is_inside_path(Path::new(root), path),
+                Pattern::Regex(_) => rule.regex.as_ref().is_some_and(|re| re.is_match(&pathstr)),
+            })
+            .cloned()
+    }
+
+    /// Checks if path is force included into backup
+    pub fn is_allowlisted(&self, path: &Path) -> bool {
+        self.action(path) == Some(RuleAction::Allow)
+    }
+
+    /// Checks if path (and its children) should not be processed at all
+    pub fn is_skipped(&self, path: &Path) -> bool {
+        self.action(path) == Some(RuleAction::Skip)
+    }
+
+    /// Checks if path should be excluded from backup regardless of git
+    pub fn is_force_excluded(&self, path: &Path) -> bool {
+        self.action(path) == Some(RuleAction::Exclude)
+    }
+
+    fn action(&self, path: &Path) -> Option<RuleAction> {
+        self.decide(path).map(|rule| rule.action)
+    }
+
+    fn push(&mut self, action: RuleAction, pattern: Pattern, source: String) -> Result<()> {
+        let (pattern, regex) = match pattern {
+            Pattern::Glob(glob) => (
+                Pattern::Glob(Self::normalize(&glob, self.case_insensitive).into_owned()),
+                None,
+            ),
+            Pattern::Path(path) => (Pattern::Path(path), None),
+            Pattern::Regex(regex) => {
+                let compiled = RegexBuilder::new(&Self::normalize(&regex, false))
+                    .case_insensitive(self.case_insensitive)
+                    .build()
+                    .with_context(|| format!("Invalid {} pattern `{}`", source, regex))?;
+                (Pattern::Regex(regex), Some(compiled))
+            }
+        };
+        self.rules.push(Rule {
+            action,
+            pattern,
+            source,
+            regex,
+        });
+        Ok(())
+    }
+
+    /// Checks glob syntax the way `glob_match` reads it. `glob_match` doesn't
+    /// report invalid globs, they just never match
+    pub fn validate_glob(glob: &str) -> std::result::Result<(), &'static str> {
+        let bytes = glob.as_bytes();
+        let mut braces = 0;
+        let mut index = 0;
+        while index < bytes.len() {
+            match bytes[index] {
+                b'\\' => {
+                    index += 1;
+                    if index == bytes.len() {
+                        return Err("trailing backslash");
+                    }
+                }
+                b'[' => {
+                    index += 1;
+                    if matches!(bytes.get(index), Some(b'^' | b'!')) {
+                        index += 1;
+                    }
+                    let mut first = true;
+                    loop {
+                        match bytes.get(index) {
+                            None => return Err("unclosed character class"),
+                            Some(b']') if !first => break,
+                            Some(b'\\') => {
+                                index += 1;
+                                if index == bytes.len() {
+                                    return Err("trailing backslash");
+                                }
+                            }
+                            _ => {}
+                        }
+                        first = false;
+                        index += 1;
+                    }
+                }
+                b'{' => {
+                    braces += 1;
+                    if braces > MAX_BRACES {
+                        return Err("too many nested braces");
+                    }
+                }
+                b'}' if braces > 0 => braces -= 1,
+                _ => {}
+            }
+            index += 1;
+        }
+        if braces > 0 {
+            return Err("unclosed brace");
+        }
+        Ok(())
+    }
+
+    /// Matches glob against normalized path. Relative globs without slash match
+    /// file name at any depth, other relative globs are anchored to the root
+    fn glob_matches(&self, glob: &str, path: &Path, normalized: &str) -> bool {
+        if self.absolute_globs || glob.starts_with('/') || glob.starts_with("**") {
+            return glob_match(glob, normalized);
+        }
+        let (glob, only_dir) = match glob.strip_suffix('/') {
+            Some(glob) => (glob, true),
+            None => (glob, false),
+        };
+        if only_dir && !path.is_dir() {
+            return false;
+        }
+        let Some(relative) = normalized
+            .strip_prefix(&self.root)
+            .and_then(|relative| relative.strip_prefix('/'))
+        else {
+            return false;
+        };
+        if glob.contains('/') {
+            glob_match(glob, relative)
+        } else {
+            relative
+                .rsplit('/')
+                .next()
+                .is_some_and(|name| glob_match(glob, name))
+        }
+    }
+
+    /// Checks if child is the root itself or is inside of it,
+    /// comparing path components with matcher normalization
+    fn is_inside_path(&self, root: &Path, child: &Path) -> bool {
+        let components = |path: &Path| -> Vec<String> {
+            path.canonicalize()
+                .unwrap_or_else(|_| path.to_path_buf())
+                .components()
+                .map(|c| {
+                    Self::normalize(&c.as_os_str().to_string_lossy(), self.case_insensitive)
+                        .into_owned()
+                })
+                .collect()
+        };
+        let root = components(root);
+        let child = components(child);
+
+        child.len() >= root.len() && child[..root.len()] == root[..]
+    }
+
+    /// Brings string to NFC form and lowercases it if needed
+    pub fn normalize(s: &str, case_insensitive: bool) -> Cow<'_, str> {
+        let s: Cow<str> = if is_nfc(s) {
+            Cow::Borrowed(s)
+        } else {
+            Cow::Owned(s.nfc().collect())
+        };
+        if case_insensitive {
+            Cow::Owned(s.to_lowercase())
+        } else {
+            s
+        }
+    }
+
+    /// Detects if volume that contains given path treats names case insensitively
+    /// by looking up one of the path components with swapped case
+    pub fn is_volume_case_insensitive(path: &Path) -> bool {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        for ancestor in path.ancestors() {
+            let (Some(parent), Some(Component::Normal(name))) =
+                (ancestor.parent(), ancestor.components().next_back())
+            else {
+                continue;
+            };
+            let name = name.to_string_lossy();
+            let swapped: String = name
+                .chars()
+                .map(|c| {
+                    if c.is_lowercase() {
+                        c.to_uppercase().collect::<String>()
+                    } else {
+                        c.to_lowercase().collect::<String>()
+                    }
+                })
+                .collect();
+            if swapped == name {
+                continue;
+            }
+            let Ok(original) = ancestor.symlink_metadata() else {
+                continue;
+            };
+            let Ok(swapped) = parent.join(swapped).symlink_metadata() else {
+                return false;
+            };
+            return original.dev() == swapped.dev() && original.ino() == swapped.ino();
+        }
+        cfg!(target_os = "macos")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_matches_regexes() {
+        let matcher = Matcher::new(
+            &Conf {
+                allowlist_regex: vec![r"/\.env(\.[^/]*)?$".to_string()],
+                skip_regex: vec![r"/out-\d+$".to_string()],
+                exclude_regex: vec![r"\.iso$".to_string()],
+                case_insensitive: Some(false),
+                disable_builtin_allowlist: true,
+                ..Conf::default()
+            },
+            Path::new("/projects"),
+        )
+        .unwrap();
+
+        assert!(matcher.is_allowlisted(Path::new("/projects/project/.env")));
+        assert!(matcher.is_allowlisted(Path::new("/projects/project/.env.local")));
+        assert!(!matcher.is_allowlisted(Path::new("/projects/project/.envrc")));
+        assert!(matcher.is_skipped(Path::new("/projects/project/out-12")));
+        assert!(!matcher.is_skipped(Path::new("/projects/project/out-12/file")));
+        assert!(matcher.is_force_excluded(Path::new("/projects/image.iso")));
+    }
+
+    #[test]
+    fn it_keeps_builtin_allowlist_at_lowest_precedence() {
+        let conf = Conf {
+            exclude_glob: vec!["*.db".to_string()],
+            case_insensitive: Some(false),
+            ..Conf::default()
+        };
+        let matcher = Matcher::new(&conf, Path::new("/projects")).unwrap();
+
+        assert!(matcher.is_allowlisted(Path::new("/projects/app/.env")));
+        assert!(matcher.is_allowlisted(Path::new("/projects/app/config/credentials.json")));
+        assert!(matcher.is_allowlisted(Path::new("/projects/keys/id_ed25519.pub")));
+        assert!(!matcher.is_allowlisted(Path::new("/projects/app/id_generator.js")));
+        assert!(matcher.is_allowlisted(Path::new("/projects/app/data.sqlite")));
+        assert!(matcher.is_force_excluded(Path::new("/projects/app/cache.db")));
+        assert_eq!(
+            matcher
+                .decide(Path::new("/projects/app/.env"))
+                .unwrap()
+                .to_string(),
+            "builtin_allowlist: allow glob .env"
+        );
+
+        let matcher = Matcher::new(
+            &Conf {
+                disable_builtin_allowlist: true,
+                ..conf
+            },
+            Path::new("/projects"),
+        )
+        .unwrap();
+        assert!(!matcher.is_allowlisted(Path::new("/projects/app/.env")));
+        assert!(!matcher.is_allowlisted(Path::new("/projects/app/data.sqlite")));
+    }
+
+    #[test]
+    fn it_fails_on_invalid_regex() {
+        let result = Matcher::new(
+            &Conf {
+                skip_regex: vec!["out-(\\d+".to_string()],
+                ..Conf::default()
+            },
+            Path::new("/projects"),
+        );
+
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "Invalid skip_regex pattern `out-(\\d+`"
+        );
+    }
+
+    #[test]
+    fn it_matches_case_insensitively() {
+        let conf = Conf {
+            allowlist_glob: vec!["**/.ENV".to_string()],
+            allowlist_path: vec!["/projects/Project/Keep".to_string()],
+            skip_regex: vec!["/NODE_MODULES$".to_string()],
+            case_insensitive: Some(true),
+            disable_builtin_allowlist: true,
+            ..Conf::default()
+        };
+        let matcher = Matcher::new(&conf, Path::new("/projects")).unwrap();
+
+        assert!(matcher.is_allowlisted(Path::new("/projects/project/.env")));
+        assert!(matcher.is_allowlisted(Path::new("/projects/project/keep/file")));
+        assert!(matcher.is_skipped(Path::new("/projects/project/node_modules")));
+
+        let matcher = Matcher::new(
+            &Conf {
+                case_insensitive: Some(false),
+                ..conf
+            },
+            Path::new("/projects"),
+        )
+        .unwrap();
+
+        assert!(!matcher.is_allowlisted(Path::new("/projects/project/.env")));
+        assert!(!matcher.is_allowlisted(Path::new("/projects/project/keep/file")));
+        assert!(!matcher.is_skipped(Path::new("/projects/project/node_modules")));
+    }
+
+    #[test]
+    fn it_matches_globs_relative_to_root() {
+        let conf = Conf {
+            allowlist_glob: vec![
+                "packages/*/dist".to_string(),
+                ".env".to_string(),
+                "/projects/app/keep.txt".to_string(),
+                "**/*.pem".to_string(),
+            ],
+            case_insensitive: Some(false),
+            ..Conf::default()
+        };
+        let matcher = Matcher::new(&conf, Path::new("/projects/app")).unwrap();
+
+        assert!(matcher.is_allowlisted(Path::new("/projects/app/packages/ui/dist")));
+        assert!(!matcher.is_allowlisted(Path::new("/projects/app/nested/packages/ui/dist")));
+        assert!(matcher.is_allowlisted(Path::new("/projects/app/.env")));
+        assert!(matcher.is_allowlisted(Path::new("/projects/app/nested/.env")));
+        assert!(!matcher.is_allowlisted(Path::new("/projects/other/.env")));
+        assert!(matcher.is_allowlisted(Path::new("/projects/app/keep.txt")));
+        assert!(matcher.is_allowlisted(Path::new("/elsewhere/key.pem")));
+
+        let matcher = Matcher::new(
+            &Conf {
+                absolute_globs: true,
+                ..conf
+            },
+            Path::new("/projects/app"),
+        )
+        .unwrap();
+
+        assert!(!matcher.is_allowlisted(Path::new("/projects/app/packages/ui/dist")));
+        assert!(!matcher.is_allowlisted(Path::new("/projects/app/.env")));
+        assert!(matcher.is_allowlisted(Path::new("/projects/app/keep.txt")));
+    }
+
+    #[test]
+    fn it_normalizes_unicode() {
+        let matcher = Matcher::new(
+            &Conf {
+                // NFC "é"
+                allowlist_glob: vec!["**/caf\u{e9}/**".to_string()],
+                case_insensitive: Some(false),
+                ..Conf::default()
+            },
+            Path::new("/projects"),
+        )
+        .unwrap();
+
+        // NFD "é"
+        assert!(matcher.is_allowlisted(Path::new("/projects/cafe\u{301}/notes.txt")));
+    }
+
+    #[test]
+    fn it_applies_last_matching_rule() {
+        let matcher = Matcher::new(
+            &Conf {
+                allowlist_glob: vec![".env*".to_string()],
+                rules: vec![
+                    RuleConf {
+                        action: RuleAction::Exclude,
+                        pattern: Pattern::Glob(".env.example".to_string()),
+                    },
+                    RuleConf {
+                        action: RuleAction::Skip,
+                        pattern: Pattern::Regex(r"/out-\d+$".to_string()),
+                    },
+                    RuleConf {
+                        action: RuleAction::Allow,
+                        pattern: Pattern::Path("/projects/out-1".to_string()),
+                    },
+                ],
+                case_insensitive: Some(false),
+                ..Conf::default()
+            },
+            Path::new("/projects"),
+        )
+        .unwrap();
+
+        assert!(matcher.is_allowlisted(Path::new("/projects/.env")));
+        assert!(matcher.is_force_excluded(Path::new("/projects/.env.example")));
+        assert!(matcher.is_skipped(Path::new("/projects/out-2")));
+        assert!(matcher.is_allowlisted(Path::new("/projects/out-1")));
+        assert_eq!(
+            matcher
+                .decide(Path::new("/projects/.env.example"))
+                .unwrap()
+                .to_string(),
+            "rules[0]: exclude glob .env.example"
+        );
+        assert!(matcher.decide(Path::new("/projects/src")).is_none());
+    }
+
+    #[test]
+    fn it_validates_globs() {
+        for glob in ["*.log", "[!a-z]x", "[]]", "{a,{b,c}}", "\\[", "a}"] {
+            assert_eq!(Matcher::validate_glob(glob), Ok(()), "{}", glob);
+        }
+        assert_eq!(
+            Matcher::validate_glob("a[b"),
+            Err("unclosed character class")
+        );
+        assert_eq!(Matcher::validate_glob("{a,b"), Err("unclosed brace"));
+        assert_eq!(Matcher::validate_glob("a\\"), Err("trailing backslash"));
+        assert_eq!(
+            Matcher::validate_glob(&"{".repeat(11)),
+            Err("too many nested braces")
+        );
+        // Invalid globs never match
+        assert!(!glob_match("a[b", "a[b"));
+        assert!(!glob_match("{a,b", "a"));
+    }
+}
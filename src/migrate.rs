@@ -0,0 +1,155 @@
+use anyhow::{bail, Result};
+use serde_json::{Map, Value};
+
+use crate::constants::CONF_VERSION;
+
+/// Option renamed in a version: `(version, old name, new name)`
+type Rename = (u32, &'static str, &'static str);
+
+/// Options renamed in each version
+const RENAMED: [Rename; 0] = [];
+
+/// Version configuration options are written for. Configuration without `version` is version 1
+pub fn version(options: &Map<String, Value>) -> Result<u32> {
+    match options.get("version") {
+        None | Some(Value::Null) => Ok(1),
+        Some(Value::Number(number)) => match number.as_u64().map(u32::try_from) {
+            Some(Ok(version)) => Ok(version),
+            _ => bail!("Invalid configuration version {}", number),
+        },
+        Some(version) => bail!("Invalid configuration version {}", version),
+    }
+}
+
+/// Upgrades options and options of profiles to the current version.
+/// Returns description of every change except the version update
+pub fn migrate(options: &mut Map<String, Value>) -> Result<Vec<String>> {
+    migrate_to(options, CONF_VERSION, &RENAMED)
+}
+
+fn migrate_to(
+    options: &mut Map<String, Value>,
+    target: u32,
+    renamed: &[Rename],
+) -> Result<Vec<String>> {
+    let version = version(options)?;
+    if version > target {
+        bail!(
+            "Configuration version {} is newer than supported version {}, update tmbliss",
+            version,
+            target
+        );
+    }
+    if version == target {
+        return Ok(vec![]);
+    }
+
+    let mut changes = upgrade(options, version, renamed, "")?;
+    if let Some(Value::Object(profiles)) = options.get_mut("profiles") {
+        for (name, profile) in profiles {
+            if let Value::Object(profile) = profile {
+                changes.extend(upgrade(
+                    profile,
+                    version,
+                    renamed,
+                    &format!("profile {}: ", name),
+                )?);
+            }
+        }
+    }
+    options.insert("version".to_string(), Value::from(target));
+    Ok(changes)
+}
+
+fn upgrade(
+    options: &mut Map<String, Value>,
+    version: u32,
+    renamed: &[Rename],
+    prefix: &str,
+) -> Result<Vec<String>> {
+    let mut changes = vec![];
+    for &(since, old, new) in renamed {
+        if since <= version {
+            continue;
+        }
+        let Some(option) = options.remove(old) else {
+            continue;
+        };
+        if options.contains_key(new) {
+            bail!("{}both `{}` and `{}` are set", prefix, old, new);
+        }
+        options.insert(new.to_string(), option);
+        changes.push(format!("{}renamed `{}` to `{}`", prefix, old, new));
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const RENAMED: [Rename; 1] = [(2, "exclude_paths", "exclude_path")];
+
+    #[test]
+    fn it_migrates_options() {
+        let Value::Object(mut options) = json!({
+            "paths": ["/Dev"],
+            "exclude_paths": ["/Dev/big"],
+            "profiles": {"work": {"exclude_paths": ["/Work/big"]}},
+        }) else {
+            unreachable!()
+        };
+
+        let changes = migrate_to(&mut options, 2, &RENAMED).unwrap();
+        assert_eq!(
+            changes,
+            [
+                "renamed `exclude_paths` to `exclude_path`",
+                "profile work: renamed `exclude_paths` to `exclude_path`",
+            ]
+        );
+        assert_eq!(
+            Value::Object(options.clone()),
+            json!({
+                "paths": ["/Dev"],
+                "exclude_path": ["/Dev/big"],
+                "profiles": {"work": {"exclude_path": ["/Work/big"]}},
+                "version": 2,
+            })
+        );
+        assert_eq!(
+            migrate_to(&mut options, 2, &RENAMED).unwrap(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn it_keeps_current_version() {
+        let Value::Object(mut options) = json!({"paths": [], "exclude_paths": ["/big"]}) else {
+            unreachable!()
+        };
+
+        assert_eq!(migrate(&mut options).unwrap(), Vec::<String>::new());
+        assert_eq!(
+            Value::Object(options),
+            json!({"paths": [], "exclude_paths": ["/big"]})
+        );
+    }
+
+    #[test]
+    fn it_rejects_newer_version() {
+        let Value::Object(mut options) = json!({"paths": [], "version": CONF_VERSION + 1}) else {
+            unreachable!()
+        };
+
+        assert!(migrate(&mut options).is_err());
+
+        let Value::Object(options) = json!({"paths": [], "version": u64::from(u32::MAX) + 1})
+        else {
+            unreachable!()
+        };
+        assert!(version(&options).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::duration;
+
+/// What made tmbliss exclude the item
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OwnerKind {
+    /// The item is ignored by git
+    #[default]
+    Gitignore,
+    /// Exclude rule: `exclude_glob`, `exclude_regex`, `rules` or `.tmbliss` directive
+    Rule,
+    /// `exclude_paths` option
+    ExcludePaths,
+    /// `tmbliss exclude` command
+    Manual,
+}
+
+/// Marker written next to exclusions made by tmbliss, so they can be told apart
+/// from exclusions made by hand or by other tools
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Owner {
+    /// Id of the run that made the exclusion
+    pub run: String,
+    pub time: DateTime<Utc>,
+    #[serde(default)]
+    pub kind: OwnerKind,
+    /// Why the item was excluded: gitignore rule, `exclude_paths` or exclude rule
+    pub reason: String,
+    /// When exclusion made with `tmbliss exclude --ttl` is lifted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl Owner {
+    /// Unique id of a run started at the given time. Runs of several profiles
+    /// start within the same moment, so ids of a process are also numbered
+    pub fn run_id(time: DateTime<Utc>) -> String {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        format!(
+            "{}-{}-{}",
+            time.format("%Y%m%dT%H%M%S%.3fZ"),
+            std::process::id(),
+            RUNS.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+
+    pub fn describe(&self, now: DateTime<Utc>) -> String {
+        let mut description = format!(
+            "run {} at {}, {}",
+            self.run,
+            self.time.with_timezone(&chrono::Local).to_rfc3339(),
+            self.reason
+        );
+        if let Some(expires) = self.expires {
+            match (expires - now).to_std() {
+                Ok(left) => {
+                    description.push_str(&format!(", expires in {}", duration::format(left)))
+                }
+                Err(_) => description.push_str(", expired"),
+            }
+        }
+        description
+    }
+
+    /// Checks if the item was excluded on purpose, with an exclude rule,
+    /// `exclude_paths` or `tmbliss exclude`, rather than because git ignores it
+    pub fn is_deliberate(&self) -> bool {
+        self.kind != OwnerKind::Gitignore
+    }
+
+    /// Checks if exclusion made with `tmbliss exclude --ttl` has expired
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// Selects exclusions by their owner
+#[derive(Default)]
+pub struct OwnerFilter {
+    /// Only exclusions made by tmbliss if true, only other exclusions if false
+    pub managed: Option<bool>,
+    /// Only exclusions made by tmbliss longer than this ago.
+    /// Age of other exclusions is unknown, so they don't match
+    pub older_than: Option<Duration>,
+}
+
+impl OwnerFilter {
+    pub fn matches(&self, owner: Option<&Owner>, now: DateTime<Utc>) -> bool {
+        if self
+            .managed
+            .is_some_and(|managed| managed != owner.is_some())
+        {
+            return false;
+        }
+        match self.older_than {
+            Some(older_than) => owner.is_some_and(|owner| {
+                (now - owner.time)
+                    .to_std()
+                    .is_ok_and(|age| age >= older_than)
+            }),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_filters_by_owner() {
+        let now = Utc::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let owner = |age: Duration| Owner {
+            run: Owner::run_id(now),
+            time: now - age,
+            kind: OwnerKind::ExcludePaths,
+            reason: "exclude_paths".to_string(),
+            expires: None,
+        };
+        let old = owner(day * 40);
+        let recent = owner(day);
+
+        let any = OwnerFilter::default();
+        assert!(any.matches(None, now));
+        assert!(any.matches(Some(&recent), now));
+
+        let managed = OwnerFilter {
+            managed: Some(true),
+            ..OwnerFilter::default()
+        };
+        assert!(!managed.matches(None, now));
+        assert!(managed.matches(Some(&recent), now));
+
+        let manual = OwnerFilter {
+            managed: Some(false),
+            ..OwnerFilter::default()
+        };
+        assert!(manual.matches(None, now));
+        assert!(!manual.matches(Some(&recent), now));
+
+        let older = OwnerFilter {
+            older_than: Some(day * 30),
+            ..OwnerFilter::default()
+        };
+        assert!(older.matches(Some(&old), now));
+        assert!(!older.matches(Some(&recent), now));
+        assert!(!older.matches(None, now));
+    }
+
+    #[test]
+    fn it_describes_expiry() {
+        let now = Utc::now();
+        let owner = |expires: DateTime<Utc>| Owner {
+            run: "run".to_string(),
+            time: now,
+            kind: OwnerKind::Manual,
+            reason: "exclude".to_string(),
+            expires: Some(expires),
+        };
+        let day = chrono::Duration::days(1);
+
+        let expiring = owner(now + day * 14);
+        assert!(expiring
+            .describe(now)
+            .ends_with(", exclude, expires in 14d"));
+        assert!(!expiring.is_expired(now));
+        assert!(expiring.is_deliberate());
+
+        let expired = owner(now - day);
+        assert!(expired.describe(now).ends_with(", expired"));
+        assert!(expired.is_expired(now));
+    }
+
+    #[test]
+    fn it_makes_unique_run_ids() {
+        let now = Utc::now();
+        assert_ne!(Owner::run_id(now), Owner::run_id(now));
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use glob_match::glob_match;
+use ignore::WalkBuilder;
+
+/// Secrets and local state that are usually ignored by git and can't be regenerated.
+/// Kept in backup unless `disable_builtin_allowlist` is set.
+/// Ignored directory that holds a match isn't excluded as a whole,
+/// its other contents are excluded instead, see [`holds_protected`]
+pub const BUILTIN_ALLOWLIST: [&str; 23] = [
+    ".env",
+    ".env.*",
+    ".envrc",
+    ".netrc",
+    ".npmrc",
+    "*.pem",
+    "*.key",
+    "*.p12",
+    "*.pfx",
+    "id_rsa*",
+    "id_ed25519*",
+    "id_ecdsa*",
+    "id_dsa*",
+    "credentials.json",
+    "secrets.*",
+    "*.keystore",
+    "*.jks",
+    "*.kdbx",
+    "*.sqlite",
+    "*.sqlite3",
+    "*.db",
+    "*.db-wal",
+    "*.db-shm",
+];
+
+/// Directories with dependencies and build output, their contents are regenerable
+const BUILD_DIRS: [&str; 20] = [
+    "node_modules",
+    "bower_components",
+    "target",
+    "build",
+    "dist",
+    "out",
+    "bin",
+    "obj",
+    ".next",
+    ".nuxt",
+    ".cache",
+    ".gradle",
+    ".terraform",
+    ".tox",
+    ".venv",
+    "venv",
+    "__pycache__",
+    "vendor",
+    "Pods",
+    "DerivedData",
+];
+
+/// Files that are generated even if they are small and recent
+const REGENERABLE_FILES: [&str; 8] = [
+    ".DS_Store",
+    "*.log",
+    "*.pyc",
+    "*.o",
+    "*.class",
+    "*.tmp",
+    "*.swp",
+    "*.pid",
+];
+
+/// Files up to this size are considered hand-made
+const MAX_SIZE: u64 = 64 * 1024;
+
+/// Files modified within this period are considered hand-made
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Directories deeper than this aren't checked
+const MAX_DEPTH: usize = 8;
+
+/// Walk of an item stops after this many entries, so huge items don't slow the run down
+const MAX_ENTRIES: usize = 10_000;
+
+/// File that is probably hand-made and not regenerable
+pub struct RiskyFile {
+    pub path: PathBuf,
+    pub size: u64,
+    pub age: Duration,
+}
+
+/// Probably hand-made files found in an item
+pub struct RiskyFiles {
+    pub item: PathBuf,
+    pub files: Vec<RiskyFile>,
+    /// Walk stopped at `MAX_ENTRIES`, there may be more files
+    pub truncated: bool,
+}
+
+impl RiskyFiles {
+    /// One line for the item, with the count and the most recently modified file
+    pub fn describe(&self) -> Option<String> {
+        let file = self.files.iter().min_by_key(|file| file.age)?;
+        Some(format!(
+            "{} has {}{} probably not regenerable files, e.g. {} ({} bytes, modified {} days ago)",
+            self.item.display(),
+            if self.truncated { "at least " } else { "" },
+            self.files.len(),
+            file.path.display(),
+            file.size,
+            file.age.as_secs() / (24 * 60 * 60)
+        ))
+    }
+}
+
+/// Finds small recently modified files in the item, or the item itself,
+/// skipping build directories and generated files
+pub fn risky_files(item: &Path, now: SystemTime) -> RiskyFiles {
+    let mut risky = RiskyFiles {
+        item: item.to_path_buf(),
+        files: vec![],
+        truncated: false,
+    };
+    if is_build_dir(item) {
+        return risky;
+    }
+    let mut entries = WalkBuilder::new(item)
+        .standard_filters(false)
+        .max_depth(Some(MAX_DEPTH))
+        .filter_entry(|entry| !is_build_dir(entry.path()))
+        .build()
+        .filter_map(|entry| entry.ok());
+    for entry in entries.by_ref().take(MAX_ENTRIES) {
+        if !entry.file_type().is_some_and(|kind| kind.is_file()) || is_regenerable(entry.path()) {
+            continue;
+        }
+        let Some(metadata) = entry.metadata().ok() else {
+            continue;
+        };
+        let Some(modified) = metadata.modified().ok() else {
+            continue;
+        };
+        let age = now.duration_since(modified).unwrap_or_default();
+        let size = metadata.len();
+        if size > 0 && size <= MAX_SIZE && age <= MAX_AGE {
+            risky.files.push(RiskyFile {
+                path: entry.into_path(),
+                size,
+                age,
+            });
+        }
+    }
+    risky.truncated = entries.next().is_some();
+    risky
+}
+
+/// Checks if a directory holds items that must stay in backup, such as files of
+/// the built-in allowlist. Build directories are regenerable as a whole, so they
+/// aren't checked, and the walk gives up after `MAX_ENTRIES` entries
+pub fn holds_protected(dir: &Path, is_protected: impl Fn(&Path) -> bool) -> bool {
+    if is_build_dir(dir) {
+        return false;
+    }
+    WalkBuilder::new(dir)
+        .standard_filters(false)
+        .max_depth(Some(MAX_DEPTH))
+        .filter_entry(|entry| !is_build_dir(entry.path()))
+        .build()
+        .filter_map(|entry| entry.ok())
+        .take(MAX_ENTRIES)
+        .any(|entry| entry.path() != dir && is_protected(entry.path()))
+}
+
+fn is_build_dir(path: &Path) -> bool {
+    path.is_dir()
+        && path
+            .file_name()
+            .is_some_and(|name| BUILD_DIRS.iter().any(|dir| name == *dir))
+}
+
+fn is_regenerable(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| {
+        let name = name.to_string_lossy();
+        REGENERABLE_FILES
+            .iter()
+            .any(|pattern| glob_match(pattern, &name))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use crate::test_utils::TestDir;
+
+    use super::*;
+
+    #[test]
+    fn it_finds_risky_files() {
+        let dir = TestDir::new();
+        let now = SystemTime::now();
+        fs::create_dir_all(dir.join("local/node_modules/pkg")).unwrap();
+        fs::create_dir_all(dir.join("local/notes")).unwrap();
+        fs::write(dir.join("local/notes/todo.md"), "call back").unwrap();
+        fs::write(dir.join("local/node_modules/pkg/index.js"), "x").unwrap();
+        fs::write(dir.join("local/.DS_Store"), "x").unwrap();
+        fs::write(dir.join("local/empty"), "").unwrap();
+        fs::write(dir.join("local/large"), vec![0; MAX_SIZE as usize + 1]).unwrap();
+        fs::write(dir.join("local/old"), "x").unwrap();
+        File::options()
+            .write(true)
+            .open(dir.join("local/old"))
+            .unwrap()
+            .set_modified(now - MAX_AGE * 2)
+            .unwrap();
+
+        fs::write(dir.join("local/notes/plan.md"), "ship it").unwrap();
+        File::options()
+            .write(true)
+            .open(dir.join("local/notes/plan.md"))
+            .unwrap()
+            .set_modified(now - MAX_AGE / 2)
+            .unwrap();
+
+        let mut risky = risky_files(&dir.join("local"), now);
+        risky.files.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(
+            risky
+                .files
+                .iter()
+                .map(|file| &file.path)
+                .collect::<Vec<_>>(),
+            [
+                &dir.join("local/notes/plan.md"),
+                &dir.join("local/notes/todo.md")
+            ]
+        );
+        assert!(!risky.truncated);
+        assert_eq!(
+            risky.describe().unwrap(),
+            format!(
+                "{} has 2 probably not regenerable files, e.g. {} (9 bytes, modified 0 days ago)",
+                dir.join("local").display(),
+                dir.join("local/notes/todo.md").display()
+            )
+        );
+        assert!(risky_files(&dir.join("local/node_modules"), now)
+            .describe()
+            .is_none());
+    }
+
+    #[test]
+    fn it_finds_protected_items() {
+        let dir = TestDir::new();
+        fs::create_dir_all(dir.join("config/keys")).unwrap();
+        fs::create_dir_all(dir.join("local/node_modules/pkg")).unwrap();
+        fs::write(dir.join("config/keys/server.pem"), "x").unwrap();
+        fs::write(dir.join("local/node_modules/pkg/test.pem"), "x").unwrap();
+        let is_protected = |path: &Path| {
+            path.file_name()
+                .is_some_and(|name| glob_match("*.pem", &name.to_string_lossy()))
+        };
+
+        assert!(holds_protected(&dir.join("config"), is_protected));
+        assert!(!holds_protected(&dir.join("local"), is_protected));
+    }
+}
@@ -2,17 +2,48 @@ use std::{error::Error, fmt::Display, path::Path};
 
 use anyhow::Result;
 
-use crate::constants::TMUTIL_ATTR;
+use crate::constants::{OWNER_ATTR, TMUTIL_ATTR};
+use crate::owner::Owner;
 
 pub struct TimeMachine {}
 
 impl TimeMachine {
-    pub fn add_exclusion(path: &Path) -> Result<(), TimeMachineError> {
-        xattr::set(path, TMUTIL_ATTR, b"1").map_err(Self::parse_error)
+    /// Excludes the path, marking exclusion with the owner if it's made by tmbliss
+    pub fn add_exclusion(path: &Path, owner: Option<&Owner>) -> Result<(), TimeMachineError> {
+        // Owner marker goes first, so there's never an exclusion made by tmbliss without it
+        if let Some(owner) = owner {
+            let value = serde_json::to_vec(owner).map_err(|e| {
+                TimeMachineError::Unknown(format!("Can't serialize owner: {}", e), None)
+            })?;
+            xattr::set(path, OWNER_ATTR, &value).map_err(Self::parse_error)?;
+        }
+        if let Err(e) = xattr::set(path, TMUTIL_ATTR, b"1") {
+            if owner.is_some() {
+                let _ = xattr::remove(path, OWNER_ATTR);
+            }
+            return Err(Self::parse_error(e));
+        }
+        Ok(())
     }
 
+    /// Removes exclusion along with its owner marker
     pub fn remove_exclusion(path: &Path) -> Result<(), TimeMachineError> {
-        xattr::remove(path, TMUTIL_ATTR).map_err(Self::parse_error)
+        xattr::remove(path, TMUTIL_ATTR).map_err(Self::parse_error)?;
+        if xattr::get(path, OWNER_ATTR)
+            .map_err(Self::parse_error)?
+            .is_some()
+        {
+            xattr::remove(path, OWNER_ATTR).map_err(Self::parse_error)?;
+        }
+        Ok(())
+    }
+
+    /// Reads owner marker of the exclusion. Exclusions made by hand or by other
+    /// tools, as well as unreadable markers, have no owner
+    pub fn owner(path: &Path) -> Result<Option<Owner>, TimeMachineError> {
+        Ok(xattr::get(path, OWNER_ATTR)
+            .map_err(Self::parse_error)?
+            .and_then(|value| serde_json::from_slice(&value).ok()))
     }
 
     pub fn is_excluded(path: &Path) -> Result<bool, TimeMachineError> {
@@ -94,6 +125,7 @@ mod tests {
     use assert_matches::assert_matches;
     use uuid::Uuid;
 
+    use crate::owner::OwnerKind;
     use crate::test_utils::TestDir;
 
     use super::*;
@@ -105,7 +137,7 @@ mod tests {
         let workspace = TestDir::new();
         let pathstr = workspace.join(format!("./text-{}.txt", Uuid::new_v4()));
         File::create(pathstr.clone()).unwrap();
-        TimeMachine::add_exclusion(&pathstr).unwrap();
+        TimeMachine::add_exclusion(&pathstr, None).unwrap();
 
         assert!(TimeMachine::is_excluded(&pathstr).unwrap());
 
@@ -115,7 +147,7 @@ mod tests {
     #[test]
     fn it_throws_inaccessible_if_cant_set_xattr() {
         let path = Path::new("./test_assets/root_file.txt");
-        let result = TimeMachine::add_exclusion(path);
+        let result = TimeMachine::add_exclusion(path, None);
 
         assert!(!TimeMachine::is_excluded(path).unwrap());
         assert_matches!(result, Err(TimeMachineError::FileInaccessible(_)));
@@ -124,7 +156,7 @@ mod tests {
     #[test]
     fn it_throws_not_found_if_cant_set_xattr() {
         let path = Path::new("./test_assets/not_a_file.txt");
-        let result = TimeMachine::add_exclusion(path);
+        let result = TimeMachine::add_exclusion(path, None);
 
         assert_matches!(result, Err(TimeMachineError::FileNotFound(_)));
     }
@@ -134,7 +166,7 @@ mod tests {
         let cwd = TestDir::new();
         let pathstr = cwd.join(format!("./text-{}.txt", Uuid::new_v4()));
         File::create(pathstr.clone()).unwrap();
-        TimeMachine::add_exclusion(&pathstr).unwrap();
+        TimeMachine::add_exclusion(&pathstr, None).unwrap();
 
         assert!(TimeMachine::is_excluded(&pathstr).unwrap());
 
@@ -145,6 +177,29 @@ mod tests {
         fs::remove_file(pathstr).unwrap();
     }
 
+    #[test]
+    fn it_marks_owner() {
+        let workspace = TestDir::new();
+        let path = workspace.join("text.txt");
+        File::create(&path).unwrap();
+        let owner = Owner {
+            run: "run".to_string(),
+            time: chrono::Utc::now(),
+            kind: OwnerKind::ExcludePaths,
+            reason: "exclude_paths".to_string(),
+            expires: None,
+        };
+
+        TimeMachine::add_exclusion(&path, Some(&owner)).unwrap();
+        assert_eq!(TimeMachine::owner(&path).unwrap(), Some(owner));
+
+        TimeMachine::remove_exclusion(&path).unwrap();
+        assert_eq!(TimeMachine::owner(&path).unwrap(), None);
+
+        TimeMachine::add_exclusion(&path, None).unwrap();
+        assert_eq!(TimeMachine::owner(&path).unwrap(), None);
+    }
+
     #[test]
     fn it_throws_inaccessible_if_cant_remove_xattr() {
         let path = Path::new("./test_assets/root_file_excluded.txt");
@@ -0,0 +1,195 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+
+use crate::conf::RuleAction;
+use crate::constants::{GLOBAL_TMBLISS_FILE, TMBLISS_FILE};
+use crate::dirs;
+use crate::matcher::Matcher;
+
+/// Parsed .tmbliss file. Uses the same grammar as .gitignore:
+/// patterns are anchored to the directory of the file, `!` negates
+/// a pattern and trailing `/` matches only directories.
+///
+/// Patterns prefixed with `+` mark items for exclusion even if
+/// git doesn't ignore them. They take precedence over other patterns of the file
+#[derive(Clone)]
+pub struct TmblissFile {
+    pub path: PathBuf,
+    allow: Gitignore,
+    exclude: Gitignore,
+}
+
+impl TmblissFile {
+    /// Reads .tmbliss file in the given directory if it exists
+    pub fn read(dir: &Path, case_insensitive: bool) -> Result<Option<Self>> {
+        let path = dir.join(TMBLISS_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Self::parse(&path, dir, case_insensitive).map(Some)
+    }
+
+    /// Reads user-global .tmbliss file. Its patterns are anchored to the given root.
+    /// Default file is `~/.config/tmbliss/tmbliss`, it's fine if it doesn't exist
+    pub fn read_global(
+        path: Option<&Path>,
+        root: &Path,
+        case_insensitive: bool,
+    ) -> Result<Option<Self>> {
+        match path {
+            Some(path) => Self::parse(path, root, case_insensitive).map(Some),
+            None => match Self::global_path() {
+                Some(path) if path.is_file() => {
+                    Self::parse(&path, root, case_insensitive).map(Some)
+                }
+                _ => Ok(None),
+            },
+        }
+    }
+
+    pub fn global_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(GLOBAL_TMBLISS_FILE))
+    }
+
+    fn parse(path: &Path, dir: &Path, case_insensitive: bool) -> Result<Self> {
+        let path = path.to_path_buf();
+        let content =
+            fs::read_to_string(&path).with_context(|| format!("Can't read {}", path.display()))?;
+
+        let root = Matcher::normalize(&dir.to_string_lossy(), false).into_owned();
+        let mut allow = GitignoreBuilder::new(&root);
+        allow.case_insensitive(case_insensitive)?;
+        let mut exclude = GitignoreBuilder::new(&root);
+        exclude.case_insensitive(case_insensitive)?;
+        for line in content.lines() {
+            let (builder, pattern) = match line.strip_prefix('+') {
+                Some(pattern) => (&mut exclude, pattern),
+                None => (&mut allow, line),
+            };
+            builder
+                .add_line(Some(path.clone()), &Matcher::normalize(pattern, false))
+                .with_context(|| format!("Invalid pattern `{}` in {}", line, path.display()))?;
+        }
+
+        Ok(Self {
+            allow: allow
+                .build()
+                .with_context(|| format!("Can't parse {}", path.display()))?,
+            exclude: exclude
+                .build()
+                .with_context(|| format!("Can't parse {}", path.display()))?,
+            path,
+        })
+    }
+
+    /// Returns action and pattern that matched the path. Whitelisted (`!pattern`) match
+    /// means that file neither allows nor excludes the path, overriding parent files
+    pub fn matched(&self, path: &Path) -> Match<(RuleAction, String)> {
+        let normalized = Matcher::normalize(&path.to_string_lossy(), false).into_owned();
+        let is_dir = path.is_dir();
+        let exclude = self.exclude.matched(&normalized, is_dir);
+        if let Match::Ignore(glob) = exclude {
+            return Match::Ignore((RuleAction::Exclude, format!("+{}", glob.original())));
+        }
+        match self.allow.matched(&normalized, is_dir) {
+            Match::Ignore(glob) => Match::Ignore((RuleAction::Skip, glob.original().to_string())),
+            Match::Whitelist(glob) => {
+                Match::Whitelist((RuleAction::Skip, glob.original().to_string()))
+            }
+            Match::None => match exclude {
+                Match::Whitelist(glob) => {
+                    Match::Whitelist((RuleAction::Exclude, format!("+{}", glob.original())))
+                }
+                _ => Match::None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+
+    use crate::test_utils::TestDir;
+
+    use super::*;
+
+    #[test]
+    fn it_parses_gitignore_grammar() {
+        let dir = TestDir::new();
+        fs::create_dir_all(dir.join("nested/data")).unwrap();
+        fs::write(
+            dir.join(TMBLISS_FILE),
+            "# comment\n/top.txt\n*.sqlite\n!keep/*.sqlite\ndata/\n\\!bang\n",
+        )
+        .unwrap();
+        let file = TmblissFile::read(dir.path(), false).unwrap().unwrap();
+
+        assert!(file.matched(&dir.join("top.txt")).is_ignore());
+        assert!(file.matched(&dir.join("nested/top.txt")).is_none());
+        assert!(file.matched(&dir.join("nested/db.sqlite")).is_ignore());
+        assert!(file.matched(&dir.join("keep/db.sqlite")).is_whitelist());
+        assert!(file.matched(&dir.join("nested/data")).is_ignore());
+        assert!(file.matched(&dir.join("nested/data.txt")).is_none());
+        assert!(file.matched(&dir.join("!bang")).is_ignore());
+    }
+
+    #[test]
+    fn it_parses_exclude_directives() {
+        let dir = TestDir::new();
+        fs::create_dir_all(dir.join("fixtures/generated")).unwrap();
+        fs::write(
+            dir.join(TMBLISS_FILE),
+            "fixtures/\n+fixtures/generated/\n+*.mov\n+!keep.mov\n\\+plus\n",
+        )
+        .unwrap();
+        let file = TmblissFile::read(dir.path(), false).unwrap().unwrap();
+
+        assert_matches!(
+            file.matched(&dir.join("fixtures/generated")),
+            Match::Ignore((RuleAction::Exclude, glob)) if glob == "+fixtures/generated/"
+        );
+        assert_matches!(
+            file.matched(&dir.join("fixtures")),
+            Match::Ignore((RuleAction::Skip, glob)) if glob == "fixtures/"
+        );
+        assert_matches!(
+            file.matched(&dir.join("recordings/demo.mov")),
+            Match::Ignore((RuleAction::Exclude, glob)) if glob == "+*.mov"
+        );
+        assert!(file.matched(&dir.join("keep.mov")).is_whitelist());
+        assert!(file.matched(&dir.join("+plus")).is_ignore());
+    }
+
+    #[test]
+    fn it_anchors_global_file_to_root() {
+        let dir = TestDir::new();
+        let global = dir.join("global_tmbliss");
+        fs::write(&global, "/top.sqlite\n*.local\n").unwrap();
+        let file = TmblissFile::read_global(Some(&global), &dir.join("root"), false)
+            .unwrap()
+            .unwrap();
+
+        assert!(file.matched(&dir.join("root/top.sqlite")).is_ignore());
+        assert!(file.matched(&dir.join("root/nested/top.sqlite")).is_none());
+        assert!(file
+            .matched(&dir.join("root/nested/.env.local"))
+            .is_ignore());
+        assert!(TmblissFile::read_global(Some(&dir.join("missing")), dir.path(), false).is_err());
+    }
+
+    #[test]
+    fn it_skips_missing_file() {
+        let dir = TestDir::new();
+
+        assert!(TmblissFile::read(dir.path(), false).unwrap().is_none());
+    }
+}
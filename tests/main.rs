@@ -5,16 +5,68 @@ mod filetree;
 
 use crate::filetree::{FileTree, FileTreeItem};
 
-use std::env::current_dir;
+use std::env::{self, current_dir};
+use std::path::Path;
+use std::sync::Once;
+use std::time::Duration;
 use test_case::test_case;
 
-use tmbliss::{Command, TMBliss, TimeMachine};
+use tmbliss::{Command, Options, TMBliss, TimeMachine};
+
+static STATE: Once = Once::new();
+
+/// Keeps run history and expiring exclusions of tests out of the user state directory
+fn isolate_state() {
+    STATE.call_once(|| {
+        env::set_var(
+            "XDG_STATE_HOME",
+            env::temp_dir().join(format!("tmbliss_test_state_{}", std::process::id())),
+        );
+    });
+}
+
+/// Runs the binary with its own state directory, so the test doesn't share
+/// history and expiring exclusions with other tests. Returns standard output
+fn tmbliss_with_state(state: &Path, args: &[&str]) -> String {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_tmbliss"))
+        .args(args)
+        .env("XDG_STATE_HOME", state)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Runs the binary with the given standard input and its own state directory.
+/// Returns standard output
+fn tmbliss_with_stdin(state: &Path, args: &[&str], input: &str) -> String {
+    use std::io::Write;
+
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_tmbliss"))
+        .args(args)
+        .env("XDG_STATE_HOME", state)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
 
 #[test_case("sec*.txt" ; "sec*.txt")]
 #[test_case("/sec*.txt" ; "/sec*.txt")]
 #[test_case("/secret.txt" ; "/secret.txt")]
 #[test_case("secret.txt" ; "secret.txt")]
 fn test_tmbliss_glob_exclusion(case: &str) {
+    isolate_state();
     let tree = FileTree::new(vec![
         FileTreeItem::Gitignore {
             key: "gitignore".to_string(),
@@ -52,12 +104,12 @@ fn test_tmbliss_glob_exclusion(case: &str) {
             .to_string_lossy()
             .to_string()],
         dry_run: false,
-        allowlist_glob: vec![],
-        allowlist_path: vec![],
-        skip_glob: vec![],
-        skip_path: vec![],
-        skip_errors: false,
-        exclude_path: vec![],
+        options: Options {
+            skip_errors: Some(false),
+            ..Default::default()
+        },
+        print0: false,
+        save_conf: None,
     };
     let result = TMBliss::run(command);
     result.unwrap();
@@ -78,6 +130,7 @@ fn test_tmbliss_glob_exclusion(case: &str) {
 
 #[test]
 fn test_tmbliss_glob_exclusion_2() {
+    isolate_state();
     let tree = FileTree::new(vec![
         FileTreeItem::Gitignore {
             key: "gitignore".to_string(),
@@ -115,12 +168,12 @@ fn test_tmbliss_glob_exclusion_2() {
             .to_string_lossy()
             .to_string()],
         dry_run: false,
-        allowlist_glob: vec![],
-        allowlist_path: vec![],
-        skip_glob: vec![],
-        skip_path: vec![],
-        skip_errors: false,
-        exclude_path: vec![],
+        options: Options {
+            skip_errors: Some(false),
+            ..Default::default()
+        },
+        print0: false,
+        save_conf: None,
     };
     let result = TMBliss::run(command);
     result.unwrap();
@@ -141,6 +194,7 @@ fn test_tmbliss_glob_exclusion_2() {
 
 #[test]
 fn test_run() {
+    isolate_state();
     let filetree = FileTree::new_test_repo();
 
     let fmap = filetree.create();
@@ -153,15 +207,17 @@ fn test_run() {
     let command = Command::Run {
         path: vec![workspace.to_string_lossy().into_owned()],
         dry_run: false,
-        allowlist_glob: vec![
-            "**/.excluded_glob".to_string(),
-            ".excluded_glob.*".to_string(),
-        ],
-        allowlist_path: vec![not_excluded_dir.to_string_lossy().into_owned()],
-        skip_glob: vec![],
-        skip_path: vec![],
-        skip_errors: false,
-        exclude_path: vec![],
+        options: Options {
+            allowlist_glob: vec![
+                "**/.excluded_glob".to_string(),
+                ".excluded_glob.*".to_string(),
+            ],
+            allowlist_path: vec![not_excluded_dir.to_string_lossy().into_owned()],
+            skip_errors: Some(false),
+            ..Default::default()
+        },
+        print0: false,
+        save_conf: None,
     };
     let result = TMBliss::run(command);
 
@@ -173,6 +229,7 @@ fn test_run() {
 
 #[test]
 fn test_exclude_paths() {
+    isolate_state();
     let filetree = FileTree::new_test_repo();
 
     let fmap = filetree.create();
@@ -182,12 +239,12 @@ fn test_exclude_paths() {
     let command = Command::Run {
         path: vec![],
         dry_run: false,
-        allowlist_glob: vec![],
-        allowlist_path: vec![],
-        skip_glob: vec![],
-        skip_path: vec![],
-        skip_errors: true,
-        exclude_path: vec![file.to_string_lossy().into_owned()],
+        options: Options {
+            exclude_path: vec![file.to_string_lossy().into_owned()],
+            ..Default::default()
+        },
+        print0: false,
+        save_conf: None,
     };
     let result = TMBliss::run(command);
 
@@ -195,8 +252,105 @@ fn test_exclude_paths() {
     assert!(TimeMachine::is_excluded(file).unwrap());
 }
 
+fn path_lists_tree() -> (FileTree, std::path::PathBuf) {
+    let tree = FileTree::new(vec![
+        FileTreeItem::Gitignore {
+            key: "gitignore".to_string(),
+            path: "".to_string(),
+            patterns: vec!["cache".to_string()],
+        },
+        FileTreeItem::File {
+            key: "cache".to_string(),
+            name: "cache/blob".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::File {
+            key: "disk.iso".to_string(),
+            name: "disk.iso".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::File {
+            key: "notes.txt".to_string(),
+            name: "notes.txt".to_string(),
+            is_excluded: false,
+        },
+    ]);
+    let state = env::temp_dir().join(format!("tmbliss_test_lists_{}", uuid::Uuid::new_v4()));
+    (tree, state)
+}
+
+#[test]
+fn test_paths_from_stdin_and_exclude_from_file() {
+    let (tree, state) = path_lists_tree();
+    let hmap = tree.create();
+    let workspace = hmap.get("__workspace").unwrap().canonicalize().unwrap();
+    let list = workspace.join("excludes.txt");
+    std::fs::write(&list, "# scratch images\n*.iso\n").unwrap();
+
+    let output = tmbliss_with_stdin(
+        &state,
+        &[
+            "list",
+            "--paths-from",
+            "-",
+            "--exclude-from",
+            &list.to_string_lossy(),
+            "--print0",
+        ],
+        &format!("{}\n", workspace.display()),
+    );
+
+    let mut paths = output.split_terminator('\0').collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(
+        paths,
+        [
+            workspace.join("cache").to_string_lossy(),
+            workspace.join("disk.iso").to_string_lossy()
+        ]
+    );
+    std::fs::remove_dir_all(state).ok();
+}
+
+#[test]
+fn test_null_separated_lists() {
+    let (tree, state) = path_lists_tree();
+    let hmap = tree.create();
+    let workspace = hmap.get("__workspace").unwrap().canonicalize().unwrap();
+    let list = workspace.join("paths");
+    std::fs::write(&list, format!("{}\0", workspace.display())).unwrap();
+
+    let output = tmbliss_with_stdin(
+        &state,
+        &[
+            "list",
+            "--paths-from",
+            &list.to_string_lossy(),
+            "--exclude-from",
+            "-",
+            "-0",
+        ],
+        &format!("{}\0", workspace.join("notes.txt").display()),
+    );
+
+    let mut lines = output
+        .lines()
+        .filter(|line| line.starts_with("new: "))
+        .collect::<Vec<_>>();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            format!("new: {}", workspace.join("cache").display()),
+            format!("new: {}", workspace.join("notes.txt").display())
+        ]
+    );
+    std::fs::remove_dir_all(state).ok();
+}
+
 #[test]
 fn test_skip_errors() {
+    isolate_state();
     let cwd = current_dir().unwrap();
 
     let dir = cwd.join("test_assets");
@@ -207,12 +361,13 @@ fn test_skip_errors() {
         let command = Command::Run {
             path: vec![dir.to_string_lossy().into_owned()],
             dry_run: false,
-            allowlist_glob: vec!["**/.DS_Store".to_string()],
-            allowlist_path: vec![],
-            skip_glob: vec![],
-            skip_path: vec![],
-            skip_errors: true,
-            exclude_path: vec![root_file.to_string_lossy().into_owned()],
+            options: Options {
+                allowlist_glob: vec!["**/.DS_Store".to_string()],
+                exclude_path: vec![root_file.to_string_lossy().into_owned()],
+                ..Default::default()
+            },
+            print0: false,
+            save_conf: None,
         };
         let result = TMBliss::run(command);
 
@@ -223,12 +378,14 @@ fn test_skip_errors() {
         let command = Command::Run {
             path: vec![dir.to_string_lossy().into_owned()],
             dry_run: false,
-            allowlist_glob: vec!["**/.DS_Store".to_string()],
-            allowlist_path: vec![],
-            skip_glob: vec![],
-            skip_path: vec![],
-            skip_errors: false,
-            exclude_path: vec![root_file.to_string_lossy().into_owned()],
+            options: Options {
+                allowlist_glob: vec!["**/.DS_Store".to_string()],
+                skip_errors: Some(false),
+                exclude_path: vec![root_file.to_string_lossy().into_owned()],
+                ..Default::default()
+            },
+            print0: false,
+            save_conf: None,
         };
         let result = TMBliss::run(command);
 
@@ -238,6 +395,7 @@ fn test_skip_errors() {
 
 #[test]
 fn test_reset() {
+    isolate_state();
     let filetree = FileTree::new_test_repo();
 
     let fmap = filetree.create();
@@ -251,12 +409,9 @@ fn test_reset() {
     TMBliss::run(Command::Run {
         path: vec![dir.to_string_lossy().into_owned()],
         dry_run: false,
-        allowlist_glob: vec![],
-        allowlist_path: vec![],
-        skip_glob: vec![],
-        skip_path: vec![],
-        skip_errors: true,
-        exclude_path: vec![],
+        options: Options::default(),
+        print0: false,
+        save_conf: None,
     })
     .unwrap();
 
@@ -269,6 +424,8 @@ fn test_reset() {
         dry_run: false,
         allowlist_glob: vec!["**/.excluded_glob".to_string()],
         allowlist_path: vec![not_excluded_path.to_string_lossy().into_owned()],
+        only_managed: false,
+        older_than: None,
     })
     .unwrap();
 
@@ -276,3 +433,605 @@ fn test_reset() {
     assert!(TimeMachine::is_excluded(&not_excluded_glob).unwrap());
     assert!(TimeMachine::is_excluded(&not_excluded_path).unwrap());
 }
+
+#[test]
+fn test_regex_rules() {
+    isolate_state();
+    let tree = FileTree::new(vec![
+        FileTreeItem::Gitignore {
+            key: "gitignore".to_string(),
+            path: "".to_string(),
+            patterns: vec![".env*".to_string(), "out-*".to_string()],
+        },
+        FileTreeItem::File {
+            key: ".env".to_string(),
+            name: ".env".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::File {
+            key: ".env.example".to_string(),
+            name: ".env.example".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::Directory {
+            key: "out-12".to_string(),
+            name: "out-12".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::Directory {
+            key: "out-latest".to_string(),
+            name: "out-latest".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::File {
+            key: "image.iso".to_string(),
+            name: "nested/image.iso".to_string(),
+            is_excluded: false,
+        },
+    ]);
+
+    let hmap = tree.create();
+
+    let command = Command::Run {
+        path: vec![hmap
+            .get("__workspace")
+            .unwrap()
+            .to_string_lossy()
+            .to_string()],
+        dry_run: false,
+        options: Options {
+            allowlist_regex: vec![r"/\.env$".to_string()],
+            skip_regex: vec![r"/out-\d+$".to_string()],
+            skip_errors: Some(false),
+            exclude_regex: vec![r"\.iso$".to_string()],
+            disable_builtin_allowlist: Some(true),
+            ..Default::default()
+        },
+        print0: false,
+        save_conf: None,
+    };
+    TMBliss::run(command).unwrap();
+
+    assert!(!TimeMachine::is_excluded(hmap.get(".env").unwrap()).unwrap());
+    assert!(TimeMachine::is_excluded(hmap.get(".env.example").unwrap()).unwrap());
+    assert!(!TimeMachine::is_excluded(hmap.get("out-12").unwrap()).unwrap());
+    assert!(TimeMachine::is_excluded(hmap.get("out-latest").unwrap()).unwrap());
+    assert!(TimeMachine::is_excluded(hmap.get("image.iso").unwrap()).unwrap());
+}
+
+#[test]
+fn test_invalid_regex() {
+    isolate_state();
+    let command = Command::Run {
+        path: vec![current_dir()
+            .unwrap()
+            .join("test_assets")
+            .to_string_lossy()
+            .into_owned()],
+        dry_run: true,
+        options: Options {
+            allowlist_regex: vec!["[".to_string()],
+            skip_errors: Some(false),
+            ..Default::default()
+        },
+        print0: false,
+        save_conf: None,
+    };
+
+    assert_eq!(
+        TMBliss::run(command).unwrap_err().to_string(),
+        "Invalid allowlist_regex pattern `[`"
+    );
+}
+
+#[test]
+fn test_tmbliss_gitignore_grammar() {
+    isolate_state();
+    let tree = FileTree::new(vec![
+        FileTreeItem::Gitignore {
+            key: "gitignore".to_string(),
+            path: "".to_string(),
+            patterns: vec!["*.sqlite".to_string(), "build/".to_string()],
+        },
+        FileTreeItem::TmBliss {
+            key: "tmbliss".to_string(),
+            path: "".to_string(),
+            patterns: vec!["*.sqlite".to_string(), "/build/".to_string()],
+        },
+        FileTreeItem::TmBliss {
+            key: "cache/tmbliss".to_string(),
+            path: "cache".to_string(),
+            patterns: vec!["!scratch.sqlite".to_string()],
+        },
+        FileTreeItem::File {
+            key: "app.sqlite".to_string(),
+            name: "nested/app.sqlite".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::File {
+            key: "cache/scratch.sqlite".to_string(),
+            name: "cache/scratch.sqlite".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::File {
+            key: "cache/other.sqlite".to_string(),
+            name: "cache/other.sqlite".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::Directory {
+            key: "build".to_string(),
+            name: "build".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::Directory {
+            key: "nested/build".to_string(),
+            name: "nested/build".to_string(),
+            is_excluded: false,
+        },
+    ]);
+
+    let hmap = tree.create();
+
+    let command = Command::Run {
+        path: vec![hmap
+            .get("__workspace")
+            .unwrap()
+            .to_string_lossy()
+            .to_string()],
+        dry_run: false,
+        options: Options {
+            skip_errors: Some(false),
+            disable_builtin_allowlist: Some(true),
+            ..Default::default()
+        },
+        print0: false,
+        save_conf: None,
+    };
+    TMBliss::run(command).unwrap();
+
+    assert!(!TimeMachine::is_excluded(hmap.get("app.sqlite").unwrap()).unwrap());
+    assert!(TimeMachine::is_excluded(hmap.get("cache/scratch.sqlite").unwrap()).unwrap());
+    assert!(!TimeMachine::is_excluded(hmap.get("cache/other.sqlite").unwrap()).unwrap());
+    assert!(!TimeMachine::is_excluded(hmap.get("build").unwrap()).unwrap());
+    assert!(TimeMachine::is_excluded(hmap.get("nested/build").unwrap()).unwrap());
+}
+
+#[test]
+fn test_builtin_allowlist_inside_ignored_directory() {
+    isolate_state();
+    let file = |name: &str| FileTreeItem::File {
+        key: name.to_string(),
+        name: name.to_string(),
+        is_excluded: false,
+    };
+    let tree = FileTree::new(vec![
+        FileTreeItem::Gitignore {
+            key: "gitignore".to_string(),
+            path: "".to_string(),
+            patterns: vec!["config/".to_string(), "local/".to_string()],
+        },
+        file("config/.env"),
+        file("config/cache.bin"),
+        file("config/nested/keys/server.pem"),
+        file("config/nested/tmp.log"),
+        file("local/notes.txt"),
+    ]);
+
+    let hmap = tree.create();
+    let workspace = hmap.get("__workspace").unwrap();
+
+    let command = Command::Run {
+        path: vec![workspace.to_string_lossy().to_string()],
+        dry_run: false,
+        options: Options {
+            skip_errors: Some(false),
+            ..Default::default()
+        },
+        print0: false,
+        save_conf: None,
+    };
+    TMBliss::run(command).unwrap();
+
+    // Directories with secrets are excluded around them instead of as a whole
+    for kept in [
+        "config",
+        "config/.env",
+        "config/nested",
+        "config/nested/keys",
+        "config/nested/keys/server.pem",
+    ] {
+        assert!(
+            !TimeMachine::is_excluded(&workspace.join(kept)).unwrap(),
+            "{}",
+            kept
+        );
+    }
+    for excluded in ["config/cache.bin", "config/nested/tmp.log", "local"] {
+        assert!(
+            TimeMachine::is_excluded(&workspace.join(excluded)).unwrap(),
+            "{}",
+            excluded
+        );
+    }
+}
+
+#[test]
+fn test_tmbliss_exclude_directives() {
+    isolate_state();
+    let tree = FileTree::new(vec![
+        FileTreeItem::TmBliss {
+            key: "tmbliss".to_string(),
+            path: "".to_string(),
+            patterns: vec!["+fixtures/generated/".to_string(), "+*.mov".to_string()],
+        },
+        FileTreeItem::Directory {
+            key: "fixtures/generated".to_string(),
+            name: "fixtures/generated".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::File {
+            key: "fixtures/generated/data.json".to_string(),
+            name: "fixtures/generated/data.json".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::File {
+            key: "fixtures/manual.json".to_string(),
+            name: "fixtures/manual.json".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::File {
+            key: "demo.mov".to_string(),
+            name: "recordings/demo.mov".to_string(),
+            is_excluded: false,
+        },
+    ]);
+
+    let hmap = tree.create();
+
+    let command = Command::Run {
+        path: vec![hmap
+            .get("__workspace")
+            .unwrap()
+            .to_string_lossy()
+            .to_string()],
+        dry_run: false,
+        options: Options {
+            skip_errors: Some(false),
+            ..Default::default()
+        },
+        print0: false,
+        save_conf: None,
+    };
+    TMBliss::run(command).unwrap();
+
+    assert!(TimeMachine::is_excluded(hmap.get("fixtures/generated").unwrap()).unwrap());
+    assert!(!TimeMachine::is_excluded(hmap.get("fixtures/generated/data.json").unwrap()).unwrap());
+    assert!(!TimeMachine::is_excluded(hmap.get("fixtures/manual.json").unwrap()).unwrap());
+    assert!(TimeMachine::is_excluded(hmap.get("demo.mov").unwrap()).unwrap());
+}
+
+#[test]
+fn test_exclude_glob_and_missing_paths() {
+    isolate_state();
+    let tree = FileTree::new(vec![
+        FileTreeItem::File {
+            key: "image.iso".to_string(),
+            name: "vm/image.iso".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::File {
+            key: "notes.txt".to_string(),
+            name: "vm/notes.txt".to_string(),
+            is_excluded: false,
+        },
+    ]);
+
+    let hmap = tree.create();
+    let workspace = hmap.get("__workspace").unwrap();
+    let missing = workspace.join("missing.bin").to_string_lossy().to_string();
+
+    let command = |missing_ok: bool| Command::Run {
+        path: vec![workspace.to_string_lossy().to_string()],
+        dry_run: false,
+        options: Options {
+            skip_errors: Some(false),
+            exclude_path: vec![missing.clone()],
+            exclude_glob: vec!["*.iso".to_string()],
+            missing_ok: Some(missing_ok),
+            ..Default::default()
+        },
+        print0: false,
+        save_conf: None,
+    };
+
+    assert!(TMBliss::run(command(false)).is_err());
+    assert!(!TimeMachine::is_excluded(hmap.get("image.iso").unwrap()).unwrap());
+
+    TMBliss::run(command(true)).unwrap();
+    assert!(TimeMachine::is_excluded(hmap.get("image.iso").unwrap()).unwrap());
+    assert!(!TimeMachine::is_excluded(hmap.get("notes.txt").unwrap()).unwrap());
+}
+
+#[test]
+fn test_safety_guards() {
+    isolate_state();
+    let tree = FileTree::new(vec![
+        FileTreeItem::File {
+            key: "image.iso".to_string(),
+            name: "vm/image.iso".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::File {
+            key: "disk.iso".to_string(),
+            name: "vm/disk.iso".to_string(),
+            is_excluded: false,
+        },
+    ]);
+
+    let hmap = tree.create();
+    let workspace = hmap.get("__workspace").unwrap();
+
+    let command = |force: bool| Command::Run {
+        path: vec![workspace.to_string_lossy().to_string()],
+        dry_run: false,
+        options: Options {
+            skip_errors: Some(false),
+            exclude_glob: vec!["*.iso".to_string()],
+            max_new_exclusions: Some(1),
+            force,
+            ..Default::default()
+        },
+        print0: false,
+        save_conf: None,
+    };
+
+    let error = TMBliss::run(command(false)).unwrap_err().to_string();
+    assert!(error.contains("2 new exclusions exceed max_new_exclusions 1"));
+    assert!(!TimeMachine::is_excluded(hmap.get("image.iso").unwrap()).unwrap());
+    assert!(!TimeMachine::is_excluded(hmap.get("disk.iso").unwrap()).unwrap());
+
+    TMBliss::run(command(true)).unwrap();
+    assert!(TimeMachine::is_excluded(hmap.get("image.iso").unwrap()).unwrap());
+    assert!(TimeMachine::is_excluded(hmap.get("disk.iso").unwrap()).unwrap());
+}
+
+#[test]
+fn test_reset_only_managed() {
+    isolate_state();
+    let tree = FileTree::new(vec![
+        FileTreeItem::Gitignore {
+            key: "gitignore".to_string(),
+            path: "".to_string(),
+            patterns: vec!["cache".to_string()],
+        },
+        FileTreeItem::Directory {
+            key: "cache".to_string(),
+            name: "cache".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::Directory {
+            key: "manual".to_string(),
+            name: "manual".to_string(),
+            is_excluded: true,
+        },
+    ]);
+
+    let hmap = tree.create();
+    let workspace = hmap.get("__workspace").unwrap();
+    let cache = hmap.get("cache").unwrap();
+    let manual = hmap.get("manual").unwrap();
+
+    TMBliss::run(Command::Run {
+        path: vec![workspace.to_string_lossy().into_owned()],
+        dry_run: false,
+        options: Options::default(),
+        print0: false,
+        save_conf: None,
+    })
+    .unwrap();
+
+    let owner = TimeMachine::owner(cache).unwrap().unwrap();
+    assert!(owner.reason.starts_with("gitignore: "));
+    assert!(owner.reason.ends_with(":1 cache"));
+    assert!(TimeMachine::owner(manual).unwrap().is_none());
+
+    let reset = |older_than: Option<Duration>| Command::Reset {
+        path: workspace.to_string_lossy().into_owned(),
+        dry_run: false,
+        allowlist_glob: vec![],
+        allowlist_path: vec![],
+        only_managed: true,
+        older_than,
+    };
+
+    TMBliss::run(reset(Some(Duration::from_secs(24 * 60 * 60)))).unwrap();
+    assert!(TimeMachine::is_excluded(cache).unwrap());
+
+    TMBliss::run(reset(None)).unwrap();
+    assert!(!TimeMachine::is_excluded(cache).unwrap());
+    assert!(TimeMachine::owner(cache).unwrap().is_none());
+    assert!(TimeMachine::is_excluded(manual).unwrap());
+}
+
+#[test]
+fn test_history() {
+    let tree = FileTree::new(vec![
+        FileTreeItem::Gitignore {
+            key: "gitignore".to_string(),
+            path: "".to_string(),
+            patterns: vec!["cache".to_string(), "dist".to_string()],
+        },
+        FileTreeItem::File {
+            key: "cache".to_string(),
+            name: "cache/blob".to_string(),
+            is_excluded: false,
+        },
+    ]);
+
+    let hmap = tree.create();
+    let workspace = hmap.get("__workspace").unwrap();
+    std::fs::write(hmap.get("cache").unwrap(), vec![0; 1000]).unwrap();
+    let state = env::temp_dir().join(format!("tmbliss_test_history_{}", uuid::Uuid::new_v4()));
+    let tmbliss = |args: &[&str]| tmbliss_with_state(&state, args);
+    let root = workspace.to_string_lossy();
+
+    tmbliss(&["run", "--path", &root, "--history-bytes"]);
+    std::fs::create_dir(workspace.join("dist")).unwrap();
+    std::fs::write(workspace.join("dist/bundle.js"), vec![0; 300]).unwrap();
+    tmbliss(&["run", "--path", &root, "--history-bytes"]);
+
+    let cache = workspace.join("cache").canonicalize().unwrap();
+    let dist = workspace.join("dist").canonicalize().unwrap();
+    assert_eq!(
+        tmbliss(&["history", "diff", "-2", "-1"])
+            .lines()
+            .skip(1)
+            .collect::<Vec<_>>(),
+        [
+            "conf: unchanged".to_string(),
+            format!("added: {}", dist.display())
+        ]
+    );
+
+    let content = std::fs::read_to_string(state.join("tmbliss/history.jsonl")).unwrap();
+    let runs = content
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(runs.len(), 2);
+    assert_eq!(runs[0]["status"], "completed");
+    assert_eq!(runs[0]["new"], 1);
+    assert_eq!(runs[0]["exclusions"], serde_json::json!([cache]));
+    assert_eq!(runs[0]["bytes"], 1000);
+    assert_eq!(runs[1]["new"], 1);
+    assert_eq!(runs[1]["excluded"], 1);
+    assert_eq!(runs[1]["exclusions"], serde_json::json!([cache, dist]));
+    assert_eq!(runs[1]["bytes"], 300);
+
+    // Aborted runs are recorded too
+    std::fs::write(workspace.join("disk.iso"), "").unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_tmbliss"))
+        .args(["run", "--path", &root, "--max-new-exclusions", "0"])
+        .args(["--exclude-glob", "*.iso"])
+        .env("XDG_STATE_HOME", &state)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let content = std::fs::read_to_string(state.join("tmbliss/history.jsonl")).unwrap();
+    let run = serde_json::from_str::<serde_json::Value>(content.lines().last().unwrap()).unwrap();
+    assert_eq!(run["status"], "aborted");
+    assert!(run["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("Aborted before excluding anything"));
+    std::fs::remove_dir_all(state).unwrap();
+}
+
+#[test]
+fn test_history_of_profiles() {
+    let tree = FileTree::new(vec![
+        FileTreeItem::Gitignore {
+            key: "gitignore".to_string(),
+            path: "".to_string(),
+            patterns: vec!["cache".to_string()],
+        },
+        FileTreeItem::File {
+            key: "cache".to_string(),
+            name: "cache/blob".to_string(),
+            is_excluded: false,
+        },
+    ]);
+
+    let hmap = tree.create();
+    let workspace = hmap.get("__workspace").unwrap();
+    let conf = workspace.join("tmbliss.json");
+    let root = workspace.to_string_lossy();
+    std::fs::write(
+        &conf,
+        serde_json::json!({
+            "paths": [root],
+            "dry_run": true,
+            "profiles": {"dev": {}, "work": {"skip_glob": ["cache"]}},
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let state = env::temp_dir().join(format!("tmbliss_test_profiles_{}", uuid::Uuid::new_v4()));
+    let tmbliss = |args: &[&str]| tmbliss_with_state(&state, args);
+
+    let conf = conf.to_string_lossy();
+    tmbliss(&[
+        "conf",
+        "--path",
+        &conf,
+        "--profile",
+        "dev",
+        "--profile",
+        "work",
+    ]);
+
+    let content = std::fs::read_to_string(state.join("tmbliss/history.jsonl")).unwrap();
+    let runs = content
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(runs.len(), 2);
+    let (dev, work) = (
+        runs[0]["run"].as_str().unwrap(),
+        runs[1]["run"].as_str().unwrap(),
+    );
+    assert_ne!(dev, work);
+    // Dry runs don't exclude anything, they only count what they would exclude
+    assert_eq!(runs[0]["new"], 0);
+    assert_eq!(runs[0]["planned"], 1);
+    assert_eq!(runs[1]["new"], 0);
+    assert_eq!(runs[1]["planned"], 0);
+    let output = tmbliss(&["history", "diff", work, dev]);
+    assert_eq!(
+        output.lines().next(),
+        Some(format!("runs: {} -> {}", work, dev).as_str())
+    );
+    assert!(!output.contains("cache"));
+    std::fs::remove_dir_all(state).unwrap();
+}
+
+#[test]
+fn test_exclude_ttl() {
+    let tree = FileTree::new(vec![
+        FileTreeItem::File {
+            key: "dataset".to_string(),
+            name: "dataset/train.csv".to_string(),
+            is_excluded: false,
+        },
+        FileTreeItem::File {
+            key: "image".to_string(),
+            name: "image/disk.img".to_string(),
+            is_excluded: false,
+        },
+    ]);
+
+    let hmap = tree.create();
+    let workspace = hmap.get("__workspace").unwrap();
+    let dataset = workspace.join("dataset");
+    let image = workspace.join("image");
+    let state = env::temp_dir().join(format!("tmbliss_test_ttl_{}", uuid::Uuid::new_v4()));
+    let tmbliss = |args: &[&str]| tmbliss_with_state(&state, args);
+
+    tmbliss(&["exclude", "--ttl", "0s", &dataset.to_string_lossy()]);
+    tmbliss(&["exclude", "--ttl", "14d", &image.to_string_lossy()]);
+    assert!(TimeMachine::is_excluded(&dataset).unwrap());
+    assert!(TimeMachine::is_excluded(&image).unwrap());
+
+    let root = workspace.to_string_lossy();
+    let output = tmbliss(&["run", "--path", &root, "--dry-run"]);
+    assert!(output.contains(&format!(
+        "expired: {}",
+        dataset.canonicalize().unwrap().display()
+    )));
+    assert!(TimeMachine::is_excluded(&dataset).unwrap());
+
+    tmbliss(&["run", "--path", &root]);
+    assert!(!TimeMachine::is_excluded(&dataset).unwrap());
+    assert!(TimeMachine::is_excluded(&image).unwrap());
+    std::fs::remove_dir_all(state).unwrap();
+}